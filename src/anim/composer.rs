@@ -1,31 +1,196 @@
-use crate::anim::layer::SkeletalLayer;
+use crate::anim::layer::{BlendMode, Crossfade, FadeSourceTime, LayerFade, SkeletalLayer};
 use std::cell::Cell;
 use crate::model::SkeletalMesh;
-use crate::anim::skeletal::sample_bear;
+use crate::anim::skeletal::{apply_additive, blend_trs, pose_skeleton, sample_local_pose, sample_morph_weights, AnimationLibrary, SkeletalAnimation, TRS};
 use math::matrix::float4x4;
+use std::rc::Rc;
 
 pub struct SkeletalComposer {
     pub global_playback_speed: Cell<f32>,
+    /// blended bottom to top
     pub layers: Vec<SkeletalLayer>,
-
-    time: f64
+    /// clips available to the layers by name
+    pub library: AnimationLibrary
 }
 
 impl SkeletalComposer {
-    pub fn new(global_playback_speed: f32, layers: Vec<SkeletalLayer>) -> SkeletalComposer {
+    pub fn new(global_playback_speed: f32, library: AnimationLibrary, layers: Vec<SkeletalLayer>) -> SkeletalComposer {
         SkeletalComposer {
             global_playback_speed: Cell::new(global_playback_speed),
             layers,
-            time: 0.0
+            library
         }
     }
 
+    /// look up a clip in the library by name
+    pub fn clip(&self, name: &str) -> Option<Rc<SkeletalAnimation>> {
+        return self.library.get(name);
+    }
+
+    /// switch a layer to the named clip, false if there is no such layer or the library has no such clip
+    pub fn set_layer_clip(&mut self, layer_index: usize, name: &str) -> bool {
+        let anim = match self.library.get(name) {
+            Some(anim) => anim,
+            None => {
+                warn!("composer: no clip named {}", name);
+                return false;
+            }
+        };
+        let layer = match self.layers.get_mut(layer_index) {
+            Some(layer) => layer,
+            None => {
+                warn!("composer: no layer {}", layer_index);
+                return false;
+            }
+        };
+        layer.anim = anim;
+        layer.time.set(0.0);
+        layer.fade = None;
+        return true;
+    }
+
+    /// crossfade a layer from its current clip to the named clip, false if there is no such layer or the library has no such clip.
+    /// playing again mid-fade fades out from the clip that was fading in, the older clip is dropped
+    pub fn play(&mut self, layer_index: usize, name: &str, crossfade: Crossfade) -> bool {
+        let anim = match self.library.get(name) {
+            Some(anim) => anim,
+            None => {
+                warn!("composer: no clip named {}", name);
+                return false;
+            }
+        };
+        let layer = match self.layers.get_mut(layer_index) {
+            Some(layer) => layer,
+            None => {
+                warn!("composer: no layer {}", layer_index);
+                return false;
+            }
+        };
+        let from = std::mem::replace(&mut layer.anim, anim);
+        layer.fade = if crossfade.duration > 0.0 {
+            Some(LayerFade { from, from_time: layer.time.get(), from_loop: layer.spec.loopanim, elapsed: 0.0, crossfade })
+        } else {
+            None
+        };
+        layer.time.set(0.0);
+        return true;
+    }
+
+    /// advance every layer by its own playback speed, looping layers wrap and the others hold their last frame
     pub fn update(&mut self, delta_time: f64) {
-        self.time = self.time + delta_time;
+        let global_speed = self.global_playback_speed.get();
+        for layer in self.layers.iter_mut() {
+            let delta = delta_time as f32 * global_speed * layer.spec.playback_speed.get();
+            layer.time.set(advance_time(layer.time.get(), delta, layer.anim.max_time, layer.spec.loopanim));
+
+            let mut finished = false;
+            if let Some(fade) = &mut layer.fade {
+                if fade.crossfade.source_time == FadeSourceTime::Advancing {
+                    fade.from_time = advance_time(fade.from_time, delta, fade.from.max_time, fade.from_loop);
+                }
+                fade.elapsed += delta_time as f32 * global_speed;
+                finished = fade.elapsed >= fade.crossfade.duration;
+            }
+            if finished {
+                layer.fade = None;
+            }
+        }
     }
 
     pub fn sample(&self, entity: &SkeletalMesh) -> Vec<float4x4> {
-        let layer = &self.layers[0];
-        return sample_bear(entity, &layer.anim, (self.time as f32) % layer.anim.max_time);
+        return pose_skeleton(entity, &self.sample_local_pose(entity));
     }
-}
\ No newline at end of file
+
+    /// blend the local pose of every active layer, starting from the rest pose
+    pub fn sample_local_pose(&self, entity: &SkeletalMesh) -> Vec<TRS> {
+        let mut pose = entity.joint_transforms.clone();
+        // accumulated weight of Blend layers per joint, the first contributing layer replaces the rest pose
+        let mut pose_weights = vec![0.0f32; pose.len()];
+        for layer in self.active_layers() {
+            let layer_weight = layer.spec.weight.get().min(1.0);
+            let mut layer_pose = sample_local_pose(entity, &layer.anim, layer.time.get());
+            if let Some(fade) = &layer.fade {
+                let from_pose = sample_local_pose(entity, &fade.from, fade.from_time);
+                let t = fade.factor();
+                for (joint, from_joint) in layer_pose.iter_mut().zip(from_pose.iter()) {
+                    *joint = blend_trs(from_joint, joint, t);
+                }
+            }
+            for (joint_idx, layer_joint) in layer_pose.iter().enumerate().take(pose.len()) {
+                let weight = match &layer.spec.mask {
+                    Some(mask) => layer_weight * mask.weight(joint_idx),
+                    None => layer_weight
+                };
+                if weight <= 0.0 {
+                    continue;
+                }
+                let joint = &mut pose[joint_idx];
+                if layer.spec.blend_mode.get() == BlendMode::Additive {
+                    *joint = apply_additive(joint, layer_joint, weight);
+                    continue;
+                }
+                let t = match layer.spec.blend_mode.get() {
+                    BlendMode::Override => weight,
+                    _ => weight / (pose_weights[joint_idx] + weight)
+                };
+                pose_weights[joint_idx] += weight;
+                *joint = blend_trs(joint, layer_joint, t);
+            }
+        }
+        return pose;
+    }
+
+    /// blend the morph target weights of every active layer like the joint pose, starting from the mesh's
+    /// default weights. clips match the mesh by node name, joint masks do not apply to morph weights
+    pub fn sample_morph_weights(&self, entity: &SkeletalMesh) -> Vec<f32> {
+        let mut blended = entity.mesh.weights.clone();
+        // accumulated weight of Blend layers, the first contributing layer replaces the default weights
+        let mut pose_weight = 0.0f32;
+        for layer in self.active_layers() {
+            let mut layer_weights = match sample_morph_weights(&layer.anim, &entity.name, layer.time.get()) {
+                Some(layer_weights) => layer_weights,
+                None => continue
+            };
+            if let Some(fade) = &layer.fade {
+                if let Some(from_weights) = sample_morph_weights(&fade.from, &entity.name, fade.from_time) {
+                    let t = fade.factor();
+                    for (weight, from_weight) in layer_weights.iter_mut().zip(from_weights.iter()) {
+                        *weight = from_weight + (*weight - from_weight) * t;
+                    }
+                }
+            }
+            let weight = layer.spec.weight.get().min(1.0);
+            if blended.len() < layer_weights.len() {
+                blended.resize(layer_weights.len(), 0.0);
+            }
+            if layer.spec.blend_mode.get() == BlendMode::Additive {
+                for (blended_weight, delta) in blended.iter_mut().zip(layer_weights.iter()) {
+                    *blended_weight += delta * weight;
+                }
+                continue;
+            }
+            let t = match layer.spec.blend_mode.get() {
+                BlendMode::Override => weight,
+                _ => weight / (pose_weight + weight)
+            };
+            pose_weight += weight;
+            for (blended_weight, layer_weight) in blended.iter_mut().zip(layer_weights.iter()) {
+                *blended_weight += (layer_weight - *blended_weight) * t;
+            }
+        }
+        return blended;
+    }
+
+    fn active_layers(&self) -> impl Iterator<Item = &SkeletalLayer> {
+        return self.layers.iter().filter(|layer| layer.spec.weight.get() > 0.0);
+    }
+}
+
+/// advance a clip time, looping clips wrap and the others hold their last frame
+fn advance_time(time: f32, delta: f32, duration: f32, loopanim: bool) -> f32 {
+    let time = time + delta;
+    if duration <= 0.0 {
+        return time;
+    }
+    return if loopanim { time.rem_euclid(duration) } else { time.max(0.0).min(duration) };
+}
@@ -0,0 +1,456 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::anim::composer::SkeletalComposer;
+use crate::anim::layer::{Crossfade, FadeCurve, FadeSourceTime};
+use crate::anim::skeletal::AnimationLibrary;
+
+/// json animation graph, clips are picked by states and transitions instead of gameplay code
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GraphDefinition {
+    #[serde(default)]
+    pub parameters: Vec<GraphParameterDefinition>,
+    pub states: Vec<GraphStateDefinition>,
+    /// state to start in, the first state when absent
+    pub entry: Option<String>,
+    #[serde(default)]
+    pub transitions: Vec<GraphTransitionDefinition>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GraphParameterDefinition {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: ParameterKind,
+    /// initial value of float and bool parameters
+    pub default: Option<serde_json::Value>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GraphStateDefinition {
+    pub name: String,
+    /// clip name in the animation library
+    pub clip: String,
+    #[serde(rename = "loop", default = "default_true")]
+    pub loopanim: bool,
+    #[serde(default = "default_one")]
+    pub speed: f32
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GraphTransitionDefinition {
+    /// source state, any state when absent
+    pub from: Option<String>,
+    pub to: String,
+    /// crossfade seconds
+    #[serde(default)]
+    pub duration: f32,
+    /// ease in-out when absent
+    pub curve: Option<FadeCurve>,
+    /// hold the outgoing pose during the fade
+    #[serde(rename = "freezeSource", default)]
+    pub freeze_source: bool,
+    /// normalized time of the source state the transition waits for, 1.0 = one full clip.
+    /// looping states check it once per loop, when playback passes it
+    #[serde(rename = "exitTime")]
+    pub exit_time: Option<f32>,
+    /// all conditions must hold
+    #[serde(default)]
+    pub conditions: Vec<GraphConditionDefinition>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GraphConditionDefinition {
+    pub parameter: String,
+    /// greater or less for floats, true (default) or false for bools, true for triggers
+    pub mode: Option<ConditionMode>,
+    /// threshold of float conditions
+    pub value: Option<f32>
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum ParameterKind {
+    Float,
+    Bool,
+    Trigger
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum ConditionMode {
+    Greater,
+    Less,
+    True,
+    False
+}
+
+fn default_true() -> bool {
+    return true;
+}
+
+fn default_one() -> f32 {
+    return 1.0;
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ParameterValue {
+    Float(f32),
+    Bool(bool),
+    /// set until a transition consumes it
+    Trigger(bool)
+}
+
+struct GraphParameter {
+    name: String,
+    value: ParameterValue
+}
+
+struct GraphState {
+    name: String,
+    clip: String,
+    loopanim: bool,
+    speed: f32
+}
+
+enum Condition {
+    Greater(usize, f32),
+    Less(usize, f32),
+    Is(usize, bool),
+    Trigger(usize)
+}
+
+struct GraphTransition {
+    from: Option<usize>,
+    to: usize,
+    crossfade: Crossfade,
+    exit_time: Option<f32>,
+    conditions: Vec<Condition>
+}
+
+/// state machine driving one composer layer from parameters
+pub struct AnimationGraph {
+    parameters: Vec<GraphParameter>,
+    states: Vec<GraphState>,
+    transitions: Vec<GraphTransition>,
+    entry: usize,
+    /// composer layer the graph plays its states on
+    layer: usize,
+    current: usize,
+    /// seconds spent in the current state, scaled by playback speed
+    state_time: f32
+}
+
+impl AnimationGraph {
+    /// parse a json graph definition and resolve it against the clips of library
+    pub fn from_json(data: &[u8], library: &AnimationLibrary, layer: usize) -> Result<AnimationGraph, GraphError> {
+        let definition: GraphDefinition = serde_json::from_slice(data).map_err(GraphError::Json)?;
+        return AnimationGraph::from_definition(&definition, library, layer);
+    }
+
+    pub fn from_definition(definition: &GraphDefinition, library: &AnimationLibrary, layer: usize) -> Result<AnimationGraph, GraphError> {
+        let mut parameters: Vec<GraphParameter> = Vec::with_capacity(definition.parameters.len());
+        for parameter in definition.parameters.iter() {
+            let invalid = |reason: String| GraphError::InvalidParameter { parameter: parameter.name.clone(), reason };
+            let default = parameter.default.as_ref();
+            let value = match parameter.kind {
+                ParameterKind::Float => ParameterValue::Float(match default {
+                    Some(value) => value.as_f64().ok_or_else(|| invalid("default is not a number".to_owned()))? as f32,
+                    None => 0.0
+                }),
+                ParameterKind::Bool => ParameterValue::Bool(match default {
+                    Some(value) => value.as_bool().ok_or_else(|| invalid("default is not a bool".to_owned()))?,
+                    None => false
+                }),
+                ParameterKind::Trigger => ParameterValue::Trigger(false)
+            };
+            parameters.push(GraphParameter { name: parameter.name.clone(), value });
+        }
+
+        let mut states: Vec<GraphState> = Vec::with_capacity(definition.states.len());
+        for state in definition.states.iter() {
+            if library.get(&state.clip).is_none() {
+                return Err(GraphError::MissingClip { state: state.name.clone(), clip: state.clip.clone() });
+            }
+            states.push(GraphState { name: state.name.clone(), clip: state.clip.clone(), loopanim: state.loopanim, speed: state.speed });
+        }
+        if states.is_empty() {
+            return Err(GraphError::NoStates);
+        }
+        let state_index = |name: &str| states.iter().position(|state| state.name == name)
+            .ok_or_else(|| GraphError::UnknownState(name.to_owned()));
+        let entry = match &definition.entry {
+            Some(entry) => state_index(entry)?,
+            None => 0
+        };
+
+        let mut transitions: Vec<GraphTransition> = Vec::with_capacity(definition.transitions.len());
+        for (transition_index, transition) in definition.transitions.iter().enumerate() {
+            let invalid = |reason: String| GraphError::InvalidTransition { transition: transition_index, reason };
+            let from = match &transition.from {
+                Some(from) => Some(state_index(from)?),
+                None => None
+            };
+            let to = state_index(&transition.to)?;
+            let curve = transition.curve.unwrap_or(FadeCurve::EaseInOut);
+            let source_time = if transition.freeze_source { FadeSourceTime::Frozen } else { FadeSourceTime::Advancing };
+
+            let mut conditions: Vec<Condition> = Vec::with_capacity(transition.conditions.len());
+            for condition in transition.conditions.iter() {
+                let parameter = parameters.iter().position(|parameter| parameter.name == condition.parameter)
+                    .ok_or_else(|| invalid(format!("unknown parameter {}", condition.parameter)))?;
+                let threshold = || condition.value.ok_or_else(|| invalid(format!("condition on {} has no value", condition.parameter)));
+                conditions.push(match (parameters[parameter].value, condition.mode) {
+                    (ParameterValue::Float(_), Some(ConditionMode::Greater)) => Condition::Greater(parameter, threshold()?),
+                    (ParameterValue::Float(_), Some(ConditionMode::Less)) => Condition::Less(parameter, threshold()?),
+                    (ParameterValue::Bool(_), None) | (ParameterValue::Bool(_), Some(ConditionMode::True)) => Condition::Is(parameter, true),
+                    (ParameterValue::Bool(_), Some(ConditionMode::False)) => Condition::Is(parameter, false),
+                    (ParameterValue::Trigger(_), None) | (ParameterValue::Trigger(_), Some(ConditionMode::True)) => Condition::Trigger(parameter),
+                    (_, mode) => return Err(invalid(format!("mode {:?} does not apply to parameter {}", mode, condition.parameter)))
+                });
+            }
+            if conditions.is_empty() && transition.exit_time.is_none() {
+                return Err(invalid("has neither conditions nor an exit time".to_owned()));
+            }
+            transitions.push(GraphTransition {
+                from,
+                to,
+                crossfade: Crossfade { duration: transition.duration, curve, source_time },
+                exit_time: transition.exit_time,
+                conditions
+            });
+        }
+
+        info!("animation graph: {} states, {} transitions, {} parameters", states.len(), transitions.len(), parameters.len());
+        return Ok(AnimationGraph { parameters, states, transitions, entry, layer, current: entry, state_time: 0.0 });
+    }
+
+    pub fn set_float(&mut self, name: &str, value: f32) {
+        self.set(name, ParameterValue::Float(value));
+    }
+
+    pub fn set_bool(&mut self, name: &str, value: bool) {
+        self.set(name, ParameterValue::Bool(value));
+    }
+
+    /// fire a trigger, it stays set until a transition consumes it
+    pub fn set_trigger(&mut self, name: &str) {
+        self.set(name, ParameterValue::Trigger(true));
+    }
+
+    pub fn parameter(&self, name: &str) -> Option<ParameterValue> {
+        return self.parameters.iter().find(|parameter| parameter.name == name).map(|parameter| parameter.value);
+    }
+
+    fn set(&mut self, name: &str, value: ParameterValue) {
+        let parameter = match self.parameters.iter_mut().find(|parameter| parameter.name == name) {
+            Some(parameter) => parameter,
+            None => {
+                warn!("animation graph: no parameter named {}", name);
+                return;
+            }
+        };
+        if std::mem::discriminant(&parameter.value) != std::mem::discriminant(&value) {
+            warn!("animation graph: parameter {} is {:?}, not {:?}", name, parameter.value, value);
+            return;
+        }
+        parameter.value = value;
+    }
+
+    /// name of the state being played
+    pub fn current_state(&self) -> &str {
+        return &self.states[self.current].name;
+    }
+
+    /// play the entry state on the graph's layer without a fade
+    pub fn start(&mut self, composer: &mut SkeletalComposer) -> Result<(), GraphError> {
+        self.enter_state(composer, self.entry, None)?;
+        self.current = self.entry;
+        self.state_time = 0.0;
+        return Ok(());
+    }
+
+    /// advance the current state and take the first transition whose exit time and conditions hold.
+    /// call before SkeletalComposer::update with the same delta time.
+    /// fails when the composer lacks the graph's layer or the target state's clip, the graph stays in its current state
+    pub fn update(&mut self, composer: &mut SkeletalComposer, delta_time: f64) -> Result<(), GraphError> {
+        let duration = composer.layers.get(self.layer).ok_or(GraphError::MissingLayer(self.layer))?.anim.max_time;
+        let previous_time = self.state_time;
+        self.state_time += delta_time as f32 * composer.global_playback_speed.get() * self.states[self.current].speed;
+        let (previous_normalized, normalized) = if duration > 0.0 {
+            (previous_time / duration, self.state_time / duration)
+        } else {
+            (0.0, 1.0)
+        };
+        let looping = self.states[self.current].loopanim;
+        let exit_time_reached = |exit_time: f32| {
+            if normalized < exit_time {
+                return false;
+            }
+            // a looping state passes exit_time, exit_time + 1, ... once per loop, the first update also counts time zero
+            return !looping || previous_time == 0.0 || (normalized - exit_time).floor() > (previous_normalized - exit_time).floor();
+        };
+
+        let transition = self.transitions.iter().position(|transition| {
+            return match transition.from {
+                Some(from) => from == self.current,
+                // any state transitions do not restart their own target
+                None => transition.to != self.current
+            } && transition.exit_time.map_or(true, |exit_time| exit_time_reached(exit_time))
+              && transition.conditions.iter().all(|condition| self.condition_holds(condition));
+        });
+        let transition_index = match transition {
+            Some(transition_index) => transition_index,
+            None => return Ok(())
+        };
+
+        let transition = &self.transitions[transition_index];
+        let (to, crossfade) = (transition.to, transition.crossfade);
+        self.enter_state(composer, to, Some(crossfade))?;
+        debug!("animation graph: {} -> {}", self.states[self.current].name, self.states[to].name);
+
+        // consume the triggers that fired the transition
+        for condition in transition.conditions.iter() {
+            if let Condition::Trigger(parameter) = condition {
+                self.parameters[*parameter].value = ParameterValue::Trigger(false);
+            }
+        }
+        self.current = to;
+        self.state_time = 0.0;
+        return Ok(());
+    }
+
+    fn condition_holds(&self, condition: &Condition) -> bool {
+        return match condition {
+            Condition::Greater(parameter, threshold) => match self.parameters[*parameter].value {
+                ParameterValue::Float(value) => value > *threshold,
+                _ => false
+            },
+            Condition::Less(parameter, threshold) => match self.parameters[*parameter].value {
+                ParameterValue::Float(value) => value < *threshold,
+                _ => false
+            },
+            Condition::Is(parameter, expected) => self.parameters[*parameter].value == ParameterValue::Bool(*expected),
+            Condition::Trigger(parameter) => self.parameters[*parameter].value == ParameterValue::Trigger(true)
+        };
+    }
+
+    fn enter_state(&self, composer: &mut SkeletalComposer, state_index: usize, crossfade: Option<Crossfade>) -> Result<(), GraphError> {
+        let state = &self.states[state_index];
+        if self.layer >= composer.layers.len() {
+            return Err(GraphError::MissingLayer(self.layer));
+        }
+        let playing = match crossfade {
+            Some(crossfade) => composer.play(self.layer, &state.clip, crossfade),
+            None => composer.set_layer_clip(self.layer, &state.clip)
+        };
+        if !playing {
+            return Err(GraphError::MissingClip { state: state.name.clone(), clip: state.clip.clone() });
+        }
+        let layer = &mut composer.layers[self.layer];
+        layer.spec.loopanim = state.loopanim;
+        layer.spec.playback_speed.set(state.speed);
+        return Ok(());
+    }
+}
+
+#[derive(Debug)]
+pub enum GraphError {
+    /// malformed graph json
+    Json(serde_json::Error),
+    /// graph without states
+    NoStates,
+    /// state name that is not defined
+    UnknownState(String),
+    /// state clip missing from the animation library
+    MissingClip { state: String, clip: String },
+    /// graph layer index that the composer does not have
+    MissingLayer(usize),
+    /// parameter with an unknown type or default, with the reason
+    InvalidParameter { parameter: String, reason: String },
+    /// transition that can not be used, with the reason
+    InvalidTransition { transition: usize, reason: String }
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::Json(e) => write!(f, "invalid animation graph json: {}", e),
+            GraphError::NoStates => write!(f, "animation graph has no states"),
+            GraphError::UnknownState(state) => write!(f, "unknown state {}", state),
+            GraphError::MissingClip { state, clip } => write!(f, "state {} plays missing clip {}", state, clip),
+            GraphError::MissingLayer(layer) => write!(f, "composer has no layer {}", layer),
+            GraphError::InvalidParameter { parameter, reason } => write!(f, "invalid parameter {}: {}", parameter, reason),
+            GraphError::InvalidTransition { transition, reason } => write!(f, "invalid transition {}: {}", transition, reason)
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_graph_transitions() {
+    use std::cell::Cell;
+    use crate::anim::layer::{BlendMode, SkeletalLayer, SkeletalLayerSpec};
+    use crate::anim::skeletal::SkeletalAnimation;
+
+    let mut library = AnimationLibrary::new();
+    for name in ["idle", "walk", "attack"].iter() {
+        library.insert(SkeletalAnimation {
+            name: name.to_string(),
+            sample_rate: 30.0,
+            num_frames: 31,
+            min_time: 0.0,
+            max_time: 1.0,
+            joints: Vec::new(),
+            morph_weights: Vec::new(),
+            additive: false
+        });
+    }
+    let json = r#"{
+        "parameters": [
+            { "name": "speed", "type": "float" },
+            { "name": "attack", "type": "trigger" }
+        ],
+        "states": [
+            { "name": "idle", "clip": "idle" },
+            { "name": "walk", "clip": "walk" },
+            { "name": "attack", "clip": "attack", "loop": false }
+        ],
+        "transitions": [
+            { "from": "idle", "to": "walk", "duration": 0.2, "conditions": [{ "parameter": "speed", "mode": "greater", "value": 0.1 }] },
+            { "to": "attack", "duration": 0.1, "conditions": [{ "parameter": "attack" }] },
+            { "from": "attack", "to": "idle", "duration": 0.3, "exitTime": 1.0 }
+        ]
+    }"#;
+    let mut graph = AnimationGraph::from_json(json.as_bytes(), &library, 0).unwrap();
+    let idle = library.get("idle").unwrap();
+    let spec = SkeletalLayerSpec {
+        loopanim: true,
+        playback_speed: Cell::new(1.0),
+        weight: Cell::new(1.0),
+        blend_mode: Cell::new(BlendMode::Override),
+        mask: None
+    };
+    let mut composer = SkeletalComposer::new(1.0, library, vec![SkeletalLayer::new(spec, idle)]);
+    graph.start(&mut composer).unwrap();
+    assert_eq!("idle", graph.current_state());
+
+    graph.set_float("speed", 1.0);
+    graph.update(&mut composer, 0.1).unwrap();
+    assert_eq!("walk", graph.current_state());
+    assert_eq!("walk", composer.layers[0].anim.name);
+
+    // triggers fire from any state once and are consumed
+    graph.set_trigger("attack");
+    graph.update(&mut composer, 0.1).unwrap();
+    assert_eq!("attack", graph.current_state());
+    assert_eq!(Some(ParameterValue::Trigger(false)), graph.parameter("attack"));
+    assert!(!composer.layers[0].spec.loopanim);
+
+    // waits for the clip to finish before returning to idle
+    graph.update(&mut composer, 0.5).unwrap();
+    assert_eq!("attack", graph.current_state());
+    graph.update(&mut composer, 0.6).unwrap();
+    assert_eq!("idle", graph.current_state());
+}
@@ -1,12 +1,173 @@
 use std::cell::Cell;
+use std::rc::Rc;
+use serde::{Deserialize, Serialize};
 use crate::anim::skeletal::SkeletalAnimation;
+use crate::model::Rig;
 
 pub struct SkeletalLayer {
     pub spec: SkeletalLayerSpec,
-    pub anim: SkeletalAnimation
+    /// clip shared with the composer's animation library
+    pub anim: Rc<SkeletalAnimation>,
+    /// playback position in seconds, advanced by the composer
+    pub time: Cell<f32>,
+    /// transition from the previous clip, see SkeletalComposer::play
+    pub fade: Option<LayerFade>
+}
+
+impl SkeletalLayer {
+    pub fn new(spec: SkeletalLayerSpec, anim: Rc<SkeletalAnimation>) -> SkeletalLayer {
+        SkeletalLayer { spec, anim, time: Cell::new(0.0), fade: None }
+    }
+}
+
+/// crossfade settings for switching a layer's clip
+#[derive(Copy, Clone, Debug)]
+pub struct Crossfade {
+    /// seconds until the incoming clip fully replaces the outgoing one
+    pub duration: f32,
+    pub curve: FadeCurve,
+    /// whether the outgoing clip keeps playing during the fade
+    pub source_time: FadeSourceTime
+}
+
+impl Crossfade {
+    /// ease-in-out fade with the outgoing clip still advancing
+    pub fn new(duration: f32) -> Crossfade {
+        Crossfade { duration, curve: FadeCurve::EaseInOut, source_time: FadeSourceTime::Advancing }
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum FadeCurve {
+    Linear,
+    /// smoothstep, starts and ends slowly
+    EaseInOut
+}
+
+impl FadeCurve {
+    /// blend factor towards the incoming clip for fade progress t in [0, 1]
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.max(0.0).min(1.0);
+        return match self {
+            FadeCurve::Linear => t,
+            FadeCurve::EaseInOut => t * t * (3.0 - 2.0 * t)
+        };
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FadeSourceTime {
+    /// the outgoing pose holds the frame it was at when the fade started
+    Frozen,
+    /// the outgoing clip keeps playing until the fade ends
+    Advancing
+}
+
+/// outgoing clip of a layer during a crossfade
+pub struct LayerFade {
+    pub from: Rc<SkeletalAnimation>,
+    pub from_time: f32,
+    /// loop flag of the outgoing clip, the layer's flag belongs to the incoming one
+    pub from_loop: bool,
+    /// seconds since the fade started
+    pub elapsed: f32,
+    pub crossfade: Crossfade
+}
+
+impl LayerFade {
+    /// blend factor from the outgoing towards the incoming clip
+    pub fn factor(&self) -> f32 {
+        if self.crossfade.duration <= 0.0 {
+            return 1.0;
+        }
+        return self.crossfade.curve.apply(self.elapsed / self.crossfade.duration);
+    }
 }
 
 pub struct SkeletalLayerSpec {
     pub loopanim: bool,
-    pub playback_speed: Cell<f32>
-}
\ No newline at end of file
+    pub playback_speed: Cell<f32>,
+    /// influence of the layer in [0, 1], layers with zero weight are skipped
+    pub weight: Cell<f32>,
+    pub blend_mode: Cell<BlendMode>,
+    /// joints the layer drives, None for the whole rig
+    pub mask: Option<JointMask>
+}
+
+/// per joint layer weight, multiplied with the layer weight
+pub struct JointMask {
+    /// weight in [0, 1] per rig joint
+    pub weights: Vec<f32>
+}
+
+/// sets the mask weight of a joint, and of every joint below it with include_descendants
+pub struct JointMaskRule {
+    pub joint: String,
+    pub weight: f32,
+    pub include_descendants: bool
+}
+
+impl JointMask {
+    /// build a mask for rig from rules applied in order, later rules override earlier ones.
+    /// joints no rule reaches have weight zero
+    pub fn from_rules(rig: &Rig, rules: &[JointMaskRule]) -> JointMask {
+        let mut weights = vec![0.0; rig.joint_count];
+        for rule in rules.iter() {
+            let joint = match rig.joint_names.iter().position(|name| *name == rule.joint) {
+                Some(joint) => joint,
+                None => {
+                    warn!("joint mask: rig has no joint named {}", rule.joint);
+                    continue;
+                }
+            };
+            let mut stack = vec![joint];
+            while let Some(joint) = stack.pop() {
+                weights[joint] = rule.weight;
+                if rule.include_descendants {
+                    stack.extend(rig.joint_children[joint].iter());
+                }
+            }
+        }
+        return JointMask { weights };
+    }
+
+    /// weight of a joint, zero for joints outside the mask's rig
+    pub fn weight(&self, joint: usize) -> f32 {
+        return self.weights.get(joint).cloned().unwrap_or(0.0);
+    }
+}
+
+/// how a layer combines with the layers below it
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BlendMode {
+    /// fades from the pose below towards this layer by its weight
+    Override,
+    /// weighted average with the pose below, weights are normalized
+    Blend,
+    /// adds the deltas of an additive clip (see make_additive) onto the pose below, scaled by weight
+    Additive
+}
+
+#[cfg(test)]
+#[test]
+fn test_joint_mask_descendants() {
+    use std::collections::HashMap;
+    use crate::model::RigRemapTable;
+    // hips -> spine -> head, hips -> leg
+    let rig = Rig {
+        joint_transforms: Vec::new(),
+        joint_count: 4,
+        joint_names: vec!["hips".to_owned(), "spine".to_owned(), "head".to_owned(), "leg".to_owned()],
+        joint_children: vec![vec![1, 3], vec![2], vec![], vec![]],
+        joint_parents: vec![0, 0, 1, 0],
+        root_joints: vec![0],
+        root_parent_transforms: Vec::new(),
+        remap_table: RigRemapTable { joints: HashMap::new() }
+    };
+    let upper_body = JointMask::from_rules(&rig, &[
+        JointMaskRule { joint: "spine".to_owned(), weight: 1.0, include_descendants: true },
+        JointMaskRule { joint: "head".to_owned(), weight: 0.5, include_descendants: false }
+    ]);
+    assert_eq!(vec![0.0, 1.0, 0.5, 0.0], upper_body.weights);
+}
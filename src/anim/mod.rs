@@ -1,3 +1,5 @@
 pub mod layer;
 pub mod composer;
-pub mod skeletal;
\ No newline at end of file
+pub mod skeletal;
+pub mod retarget;
+pub mod graph;
\ No newline at end of file
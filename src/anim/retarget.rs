@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+
+use math::matrix::{float4x4, matrix4x4_identity, matrix4x4_inverse, matrix4x4_mul};
+use math::quaternion::quaternion;
+use math::vector::float3;
+
+use crate::anim::skeletal::{conjugate, length, SkeletalAnimation, TRS};
+use crate::model::{Joint, Rig};
+
+/// joint correspondence between the rig an animation was authored for and the rig it drives
+pub struct RetargetMap {
+    /// source joint index per target joint, None for target joints without a match
+    pub joints: Vec<Option<usize>>,
+    /// target / source skeleton size in world units, scales root translations and
+    /// translations of joints without a rest offset
+    pub proportion: f32
+}
+
+impl RetargetMap {
+    /// match joints by name, aliases map source joint names to target joint names
+    pub fn by_name(source: &Rig, target: &Rig, aliases: &HashMap<String, String>) -> RetargetMap {
+        let mut joints: Vec<Option<usize>> = vec![None; target.joint_count];
+        for (source_index, source_name) in source.joint_names.iter().enumerate() {
+            let target_name = aliases.get(source_name).unwrap_or(source_name);
+            if let Some(target_index) = target.joint_names.iter().position(|name| name == target_name) {
+                joints[target_index] = Some(source_index);
+            }
+        }
+
+        // compare skeleton sizes over the matched bones, in world units so armature scales count
+        let (source_scale, target_scale) = (armature_scale(source), armature_scale(target));
+        let (mut source_length, mut target_length) = (0.0f32, 0.0f32);
+        for (target_index, source_index) in joints.iter().enumerate() {
+            if let Some(source_index) = source_index {
+                source_length += length(&source.joint_transforms[*source_index].translation) * source_scale;
+                target_length += length(&target.joint_transforms[target_index].translation) * target_scale;
+            }
+        }
+        let proportion = if source_length > 0.0 && target_length > 0.0 { target_length / source_length } else { 1.0 };
+
+        let matched = joints.iter().filter(|joint| joint.is_some()).count();
+        info!("retarget: matched {} of {} target joints, proportion {}", matched, target.joint_count, proportion);
+        for (target_index, source_index) in joints.iter().enumerate() {
+            if source_index.is_none() {
+                debug!("retarget: target joint {} has no source joint, holds its rest pose", target.joint_names[target_index]);
+            }
+        }
+        return RetargetMap { joints, proportion };
+    }
+}
+
+/// resample an animation of the source rig onto the target rig.
+/// each joint plays the source motion relative to the source rest pose on top of the target rest pose,
+/// translations are scaled by the bone length ratio so differently proportioned rigs keep their shape.
+/// root joint translations move through the root parent transforms of both rigs,
+/// root rotations assume both armatures share their orientation
+pub fn retarget_animation(anim: &SkeletalAnimation, map: &RetargetMap, source: &Rig, target: &Rig) -> SkeletalAnimation {
+    let mut joints: Vec<Vec<TRS>> = Vec::with_capacity(target.joint_count);
+    for target_index in 0..target.joint_count {
+        let target_rest = &target.joint_transforms[target_index];
+        let frames = match map.joints[target_index] {
+            Some(source_index) => {
+                let source_rest = &source.joint_transforms[source_index];
+                // source parent space to target parent space, for target roots only
+                let root_delta = root_parent_transform(target, target_index).map(|target_parent| {
+                    let source_parent = root_parent_transform(source, source_index).unwrap_or_else(matrix4x4_identity);
+                    matrix4x4_mul(&matrix4x4_inverse(&target_parent), &source_parent)
+                });
+                anim.joints[source_index].iter()
+                    .map(|frame| retarget_frame(frame, source_rest, target_rest, root_delta.as_ref(), map.proportion))
+                    .collect()
+            },
+            None => vec![rest_trs(target_rest); anim.num_frames]
+        };
+        joints.push(frames);
+    }
+    return SkeletalAnimation {
+        name: anim.name.clone(),
+        sample_rate: anim.sample_rate,
+        num_frames: anim.num_frames,
+        min_time: anim.min_time,
+        max_time: anim.max_time,
+        joints,
+        morph_weights: anim.morph_weights.clone(),
+        additive: anim.additive
+    };
+}
+
+fn retarget_frame(frame: &TRS, source_rest: &Joint, target_rest: &Joint, root_delta: Option<&float4x4>, proportion: f32) -> TRS {
+    // rotation delta from the source rest pose, applied to the target rest pose
+    let delta_rotation = quaternion::mul(&conjugate(&source_rest.rotation), &frame.rotation);
+    let rotation = quaternion::mul(&target_rest.rotation, &delta_rotation);
+
+    let delta_translation = frame.translation + source_rest.translation * -1.0;
+    let translation = match root_delta {
+        // roots keep their world motion, scaled to the target skeleton size
+        Some(root_delta) => target_rest.translation + transform_vector(root_delta, &delta_translation) * proportion,
+        None => {
+            // bone length ratio, falls back to the skeleton proportion for joints at their parent's origin
+            let source_length = length(&source_rest.translation);
+            let ratio = if source_length > 1e-5 { length(&target_rest.translation) / source_length } else { proportion };
+            target_rest.translation + delta_translation * ratio
+        }
+    };
+
+    let scale = float3::new(target_rest.scale.x * frame.scale.x / source_rest.scale.x,
+                            target_rest.scale.y * frame.scale.y / source_rest.scale.y,
+                            target_rest.scale.z * frame.scale.z / source_rest.scale.z);
+    return TRS { translation, rotation, scale };
+}
+
+fn rest_trs(joint: &Joint) -> TRS {
+    return TRS { translation: joint.translation, rotation: joint.rotation, scale: joint.scale };
+}
+
+/// world transform of the node above a root joint, None for joints with a parent joint
+fn root_parent_transform(rig: &Rig, joint: usize) -> Option<float4x4> {
+    let root = rig.root_joints.iter().position(|&root_joint| root_joint == joint)?;
+    return Some(rig.root_parent_transforms.get(root).cloned().unwrap_or_else(matrix4x4_identity));
+}
+
+/// uniform scale of the node above the first root joint, the armature scale of exported rigs
+fn armature_scale(rig: &Rig) -> f32 {
+    return match rig.root_parent_transforms.get(0) {
+        Some(parent) => length(&transform_vector(parent, &float3::new(1.0, 0.0, 0.0))),
+        None => 1.0
+    };
+}
+
+/// apply the rotation and scale of a transform to a vector, ignoring its translation
+fn transform_vector(m: &float4x4, v: &float3) -> float3 {
+    return float3::new(m.m[0][0] * v.x + m.m[0][1] * v.y + m.m[0][2] * v.z,
+                       m.m[1][0] * v.x + m.m[1][1] * v.y + m.m[1][2] * v.z,
+                       m.m[2][0] * v.x + m.m[2][1] * v.y + m.m[2][2] * v.z);
+}
+
+#[cfg(test)]
+#[test]
+fn test_retarget_by_name() {
+    use crate::model::RigRemapTable;
+    fn make_rig(names: &[&str], bone_length: f32) -> Rig {
+        let joints: Vec<Joint> = (0..names.len()).map(|index| Joint {
+            index,
+            parent_index: if index == 0 { 0 } else { index - 1 },
+            translation: if index == 0 { float3::zero() } else { float3::new(0.0, bone_length, 0.0) },
+            rotation: quaternion::identity(),
+            scale: float3::one()
+        }).collect();
+        return Rig {
+            joint_transforms: joints,
+            joint_count: names.len(),
+            joint_names: names.iter().map(|name| name.to_string()).collect(),
+            joint_children: (0..names.len()).map(|index| if index + 1 < names.len() { vec![index + 1] } else { vec![] }).collect(),
+            joint_parents: (0..names.len()).map(|index| if index == 0 { 0 } else { index - 1 }).collect(),
+            root_joints: vec![0],
+            root_parent_transforms: vec![math::matrix::matrix4x4_identity()],
+            remap_table: RigRemapTable { joints: HashMap::new() }
+        };
+    }
+    let source = make_rig(&["hips", "spine", "tail"], 1.0);
+    let target = make_rig(&["root", "spine", "head"], 2.0);
+    let mut aliases = HashMap::new();
+    aliases.insert("hips".to_owned(), "root".to_owned());
+    let map = RetargetMap::by_name(&source, &target, &aliases);
+    assert_eq!(vec![Some(0), Some(1), None], map.joints);
+    assert_eq!(2.0, map.proportion);
+
+    // root moves up one source unit, spine bone stretched by half a source unit
+    let moved = |translation: float3| TRS { translation, rotation: quaternion::identity(), scale: float3::one() };
+    let anim = SkeletalAnimation {
+        name: "test".to_owned(),
+        sample_rate: 30.0,
+        num_frames: 1,
+        min_time: 0.0,
+        max_time: 0.0,
+        joints: vec![vec![moved(float3::new(0.0, 1.0, 0.0))], vec![moved(float3::new(0.0, 1.5, 0.0))], vec![moved(float3::zero())]],
+        morph_weights: Vec::new(),
+        additive: false
+    };
+    let retargeted = retarget_animation(&anim, &map, &source, &target);
+    assert_eq!(2.0, retargeted.joints[0][0].translation.y);
+    assert_eq!(3.0, retargeted.joints[1][0].translation.y);
+    // unmatched joints hold the target rest pose
+    assert_eq!(2.0, retargeted.joints[2][0].translation.y);
+
+    // a half scale source armature moves its root half as far in world units
+    let mut scaled_source = make_rig(&["hips", "spine", "tail"], 1.0);
+    scaled_source.root_parent_transforms = vec![math::matrix::matrix4x4_scale(&float3::new(0.5, 0.5, 0.5))];
+    let map = RetargetMap::by_name(&scaled_source, &target, &aliases);
+    assert_eq!(4.0, map.proportion);
+    let retargeted = retarget_animation(&anim, &map, &scaled_source, &target);
+    assert_eq!(2.0, retargeted.joints[0][0].translation.y);
+}
@@ -4,6 +4,7 @@ use math::matrix::{float4x4, matrix4x4_transpose, matrix4x4_identity, matrix4x4_
 use crate::model::{SkeletalMesh, Rig};
 use crate::render::gl_geometry;
 use std::collections::HashMap;
+use std::rc::Rc;
 use math::inverse_lerp;
 
 /// densely packed joint transforms @ sample_rate
@@ -15,7 +16,67 @@ pub struct SkeletalAnimation {
     pub min_time: f32,
     pub max_time: f32,
     /// vec of joint indices to vec of TRS per frame
-    pub joints: Vec<Vec<TRS>>
+    pub joints: Vec<Vec<TRS>>,
+    /// morph target weights per animated mesh node, empty when the clip does not animate weights
+    pub morph_weights: Vec<MorphWeightTrack>,
+    /// frames are deltas from a reference pose, see make_additive
+    pub additive: bool
+}
+
+/// morph target weights of one mesh node
+#[derive(Clone)]
+pub struct MorphWeightTrack {
+    /// name of the morphed node, matches SkeletalMesh::name
+    pub node: String,
+    /// weight per morph target per frame
+    pub frames: Vec<Vec<f32>>
+}
+
+/// named animation clips sharing one rig, in load order
+pub struct AnimationLibrary {
+    clips: HashMap<String, Rc<SkeletalAnimation>>,
+    names: Vec<String>
+}
+
+impl AnimationLibrary {
+    pub fn new() -> AnimationLibrary {
+        AnimationLibrary { clips: HashMap::new(), names: Vec::new() }
+    }
+
+    /// add a clip under its name, replacing any clip with the same name
+    pub fn insert(&mut self, anim: SkeletalAnimation) {
+        if self.clips.contains_key(&anim.name) {
+            warn!("animation library: replacing clip {}", anim.name);
+        } else {
+            self.names.push(anim.name.clone());
+        }
+        self.clips.insert(anim.name.clone(), Rc::new(anim));
+    }
+
+    /// move every clip of other into this library, e.g. clips exported to separate files
+    pub fn extend(&mut self, other: AnimationLibrary) {
+        let mut other = other;
+        for name in other.names.iter() {
+            let anim = other.clips.remove(name).unwrap();
+            if !self.clips.contains_key(name) {
+                self.names.push(name.clone());
+            }
+            self.clips.insert(name.clone(), anim);
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Rc<SkeletalAnimation>> {
+        return self.clips.get(name).cloned();
+    }
+
+    /// clip names in load order
+    pub fn names(&self) -> &Vec<String> {
+        return &self.names;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.names.len();
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -30,15 +91,20 @@ impl Default for TRS {
     }
 }
 
+/// keyframe interpolation, matches the gltf sampler interpolation modes
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum InterpMethod {
     Step, Linear, CubicSpline
 }
 
 pub fn sample_bear(mesh: &SkeletalMesh, anim: &SkeletalAnimation, time: f32) -> Vec<float4x4> {
-    let mut joint_matrices = Vec::new();
-    for joint in 0..mesh.rig.joint_count {
-        joint_matrices.push(mesh.joint_local_transforms[joint]);
-    }
+    return pose_skeleton(mesh, &sample_local_pose(mesh, anim, time));
+}
+
+/// local joint transforms of anim at time, joints the clip does not cover keep their rest pose
+/// (or no change for additive clips)
+pub fn sample_local_pose(mesh: &SkeletalMesh, anim: &SkeletalAnimation, time: f32) -> Vec<TRS> {
+    let mut pose = if anim.additive { vec![TRS::default(); mesh.rig.joint_count] } else { mesh.joint_transforms.clone() };
 
     let time_step = 1f32 / anim.sample_rate;
     for joint_idx in 0..anim.joints.len() {
@@ -60,20 +126,150 @@ pub fn sample_bear(mesh: &SkeletalMesh, anim: &SkeletalAnimation, time: f32) ->
         let translation = float3::lerp(&frame_left.translation, &frame_right.translation, alpha);
         let rotation = quaternion::slerp(&frame_left.rotation, &frame_right.rotation, alpha);
         let scale = float3::lerp(&frame_left.scale, &frame_right.scale, alpha);
+        pose[joint_idx] = TRS { translation, rotation, scale };
+    }
+    return pose;
+}
 
-        let joint_trs = mesh.joint_transforms[joint_idx];
-        /*let transform = matrix4x4_trs(&(joint_trs.translation + translation),
-                                      &quaternion::mul(&joint_trs.rotation, &rotation),
-                                      &(joint_trs.scale * scale));// todo additive or multiplicative?*/
-        //let transform = matrix4x4_mul(&matrix4x4_trs(&translation, &rotation, &scale), &mesh.joint_local_transforms[joint_idx]);
-        let transform = matrix4x4_trs(&translation, &rotation, &scale);
-        joint_matrices[joint_idx] = transform;
+/// skinning matrices (transposed for upload) from local joint transforms
+pub fn pose_skeleton(mesh: &SkeletalMesh, pose: &Vec<TRS>) -> Vec<float4x4> {
+    let mut joint_matrices: Vec<float4x4> = pose.iter()
+        .map(|trs| matrix4x4_trs(&trs.translation, &trs.rotation, &trs.scale))
+        .collect();
+    for (&root_joint, parent_transform) in mesh.rig.root_joints.iter().zip(mesh.rig.root_parent_transforms.iter()) {
+        pose_hierarchy(&mesh.rig, &mut joint_matrices, &mesh.inverse_bind_matrices,
+                       root_joint, parent_transform);
     }
+    return joint_matrices;
+}
 
-    pose_hierarchy(&mesh.rig, &mut joint_matrices, &mesh.inverse_bind_matrices,
-                   0, &matrix4x4_identity());
+/// interpolate between two local transforms, rotations use normalized lerp along the shortest arc
+pub fn blend_trs(a: &TRS, b: &TRS, t: f32) -> TRS {
+    return TRS {
+        translation: float3::lerp(&a.translation, &b.translation, t),
+        rotation: nlerp(&a.rotation, &b.rotation, t),
+        scale: float3::lerp(&a.scale, &b.scale, t)
+    };
+}
 
-    return joint_matrices;
+pub fn nlerp(a: &quaternion, b: &quaternion, t: f32) -> quaternion {
+    let dot = a.x * b.x + a.y * b.y + a.z * b.z + a.w * b.w;
+    let sign = if dot < 0.0 { -1.0 } else { 1.0 };
+    let x = a.x + (b.x * sign - a.x) * t;
+    let y = a.y + (b.y * sign - a.y) * t;
+    let z = a.z + (b.z * sign - a.z) * t;
+    let w = a.w + (b.w * sign - a.w) * t;
+    let length = (x * x + y * y + z * z + w * w).sqrt();
+    if length == 0.0 {
+        return *a;
+    }
+    return quaternion::new(x / length, y / length, z / length, w / length);
+}
+
+/// pose an additive clip is measured against
+pub enum AdditiveReference<'a> {
+    /// the clip's own first frame
+    FirstFrame,
+    /// the first frame of another clip on the same rig, e.g. an idle
+    Clip(&'a SkeletalAnimation)
+}
+
+/// convert a clip into deltas from a reference pose, for layers with BlendMode::Additive.
+/// translation deltas are offsets, rotation deltas are inverse(reference) * rotation and scale deltas ratios
+pub fn make_additive(anim: &SkeletalAnimation, reference: AdditiveReference) -> SkeletalAnimation {
+    let reference_anim = match reference {
+        AdditiveReference::FirstFrame => anim,
+        AdditiveReference::Clip(clip) => clip
+    };
+    let joints = anim.joints.iter().enumerate().map(|(joint_idx, frames)| {
+        let reference_frame = reference_anim.joints.get(joint_idx).and_then(|frames| frames.get(0)).cloned().unwrap_or_default();
+        frames.iter().map(|frame| {
+            let rotation = quaternion::mul(&conjugate(&reference_frame.rotation), &frame.rotation);
+            TRS {
+                translation: frame.translation + reference_frame.translation * -1.0,
+                rotation,
+                scale: float3::new(frame.scale.x / reference_frame.scale.x, frame.scale.y / reference_frame.scale.y,
+                                   frame.scale.z / reference_frame.scale.z)
+            }
+        }).collect()
+    }).collect();
+    let morph_weights = anim.morph_weights.iter().map(|track| {
+        let reference_weights = reference_anim.morph_weights.iter()
+            .find(|reference| reference.node == track.node)
+            .and_then(|reference| reference.frames.get(0));
+        let frames = track.frames.iter().map(|weights| {
+            weights.iter().enumerate()
+                .map(|(i, weight)| weight - reference_weights.and_then(|reference| reference.get(i)).cloned().unwrap_or(0.0))
+                .collect()
+        }).collect();
+        MorphWeightTrack { node: track.node.clone(), frames }
+    }).collect();
+    return SkeletalAnimation {
+        name: anim.name.clone(),
+        sample_rate: anim.sample_rate,
+        num_frames: anim.num_frames,
+        min_time: anim.min_time,
+        max_time: anim.max_time,
+        joints,
+        morph_weights,
+        additive: true
+    };
+}
+
+/// apply a weighted additive delta on top of a base transform
+pub fn apply_additive(base: &TRS, delta: &TRS, weight: f32) -> TRS {
+    let rotation = nlerp(&quaternion::identity(), &delta.rotation, weight);
+    let one = float3::one();
+    return TRS {
+        translation: base.translation + delta.translation * weight,
+        rotation: quaternion::mul(&base.rotation, &rotation),
+        scale: base.scale * float3::lerp(&one, &delta.scale, weight)
+    };
+}
+
+/// inverse of a unit quaternion
+pub(crate) fn conjugate(q: &quaternion) -> quaternion {
+    return quaternion::new(-q.x, -q.y, -q.z, q.w);
+}
+
+/// v' = v + 2w(q x v) + 2(q x (q x v))
+pub(crate) fn rotate(q: &quaternion, v: &float3) -> float3 {
+    let u = float3::new(q.x, q.y, q.z);
+    let uv = cross(&u, v);
+    let uuv = cross(&u, &uv);
+    return *v + uv * (2.0 * q.w) + uuv * 2.0;
+}
+
+pub(crate) fn cross(a: &float3, b: &float3) -> float3 {
+    return float3::new(a.y * b.z - a.z * b.y, a.z * b.x - a.x * b.z, a.x * b.y - a.y * b.x);
+}
+
+pub(crate) fn length(v: &float3) -> f32 {
+    return (v.x * v.x + v.y * v.y + v.z * v.z).sqrt();
+}
+
+/// unit length v, zero vectors are returned unchanged
+pub(crate) fn normalize(v: &float3) -> float3 {
+    let length = length(v);
+    if length == 0.0 {
+        return *v;
+    }
+    return *v * (1.0 / length);
+}
+
+/// morph target weights of the named mesh node of anim at time, None when the clip does not animate its weights
+pub fn sample_morph_weights(anim: &SkeletalAnimation, node: &str, time: f32) -> Option<Vec<f32>> {
+    let frames = &anim.morph_weights.iter().find(|track| track.node == node)?.frames;
+    if frames.is_empty() {
+        return None;
+    }
+    let time_step = 1f32 / anim.sample_rate;
+    let index_left = ((time / time_step).floor() as usize).min(frames.len() - 1);
+    let index_right = (index_left + 1).min(frames.len() - 1);
+    let alpha = inverse_lerp(time_step * (index_left as f32), time_step * ((index_left + 1) as f32), time);
+
+    let (left, right) = (&frames[index_left], &frames[index_right]);
+    return Some(left.iter().zip(right.iter()).map(|(l, r)| l + (r - l) * alpha).collect());
 }
 
 pub fn pose_hierarchy(rig: &Rig, joint_matrices: &mut Vec<float4x4>, inverse_bind_matrices: &Vec<float4x4>,
@@ -85,4 +281,35 @@ pub fn pose_hierarchy(rig: &Rig, joint_matrices: &mut Vec<float4x4>, inverse_bin
     }
     let posed = matrix4x4_mul(&joint_world, &inverse_bind_matrices[joint_idx]);
     joint_matrices[joint_idx] = matrix4x4_transpose(&posed);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+#[test]
+fn test_make_additive() {
+    let frame = |y: f32, angle: f32| TRS {
+        translation: float3::new(0.0, y, 0.0),
+        rotation: quaternion::new(0.0, (angle * 0.5).sin(), 0.0, (angle * 0.5).cos()),
+        scale: float3::one()
+    };
+    let anim = SkeletalAnimation {
+        name: "breathe".to_owned(),
+        sample_rate: 30.0,
+        num_frames: 2,
+        min_time: 0.0,
+        max_time: 2.0 / 30.0,
+        joints: vec![vec![frame(1.0, 0.5), frame(1.5, 0.75)]],
+        morph_weights: Vec::new(),
+        additive: false
+    };
+    let additive = make_additive(&anim, AdditiveReference::FirstFrame);
+    assert!(additive.additive);
+    assert_eq!(0.0, additive.joints[0][0].translation.y);
+    assert!((additive.joints[0][0].rotation.w - 1.0).abs() < 1e-6);
+
+    // the delta applied at full weight onto the reference reproduces the clip
+    let applied = apply_additive(&anim.joints[0][0], &additive.joints[0][1], 1.0);
+    let expected = &anim.joints[0][1];
+    assert!((applied.translation.y - expected.translation.y).abs() < 1e-6);
+    assert!((applied.rotation.y - expected.rotation.y).abs() < 1e-5);
+    assert!((applied.rotation.w - expected.rotation.w).abs() < 1e-5);
+}
@@ -7,6 +7,8 @@ use std::ffi::CString;
 use std::io;
 
 pub struct Asset {
+    /// path relative to the asset root
+    pub path: String,
     #[cfg(target_os = "android")]
     pub android_asset: AndroidAsset
 }
@@ -26,9 +28,8 @@ impl Asset {
 pub fn load_asset(file: &str) -> Option<Asset> {
     #[cfg(target_os = "android")]
     {
-            return Option::Some(Asset {
-                android_asset: native_activity().asset_manager().open(CString::new(file).unwrap().as_c_str()).unwrap()
-            });
+            return native_activity().asset_manager().open(CString::new(file).unwrap().as_c_str())
+                .map(|android_asset| Asset { path: file.to_owned(), android_asset });
     }
     return Option::None;
 }
\ No newline at end of file
@@ -0,0 +1,126 @@
+use math::vector::{float3, float4};
+
+use crate::gltf::{load_image_data, GltfError, GltfFile, GltfTextureInfo};
+use crate::model::{AlphaMode, Material, TextureRef, TextureSampler};
+
+/// gl texture enums, gltf sampler values use the same numbers
+const GL_REPEAT: u32 = 0x2901;
+
+/// load every material in the file, indexed like Mesh::material
+pub fn load_materials(file: &GltfFile) -> Result<Vec<Material>, GltfError> {
+    let material_count = file.materials.as_ref().map_or(0, |materials| materials.len());
+    return (0..material_count).map(|material_index| load_material(material_index, file)).collect();
+}
+
+/// load a single material, the gltf default material for None
+pub fn load_material_or_default(material_index: Option<usize>, file: &GltfFile) -> Result<Material, GltfError> {
+    return match material_index {
+        Some(material_index) => load_material(material_index, file),
+        None => Ok(Material::default())
+    };
+}
+
+pub fn load_material(material_index: usize, file: &GltfFile) -> Result<Material, GltfError> {
+    let gltf_material = file.materials.as_ref()
+        .and_then(|materials| materials.get(material_index))
+        .ok_or_else(|| GltfError::InvalidMaterial { material: material_index, reason: "does not exist".to_owned() })?;
+    let invalid = |reason: String| GltfError::InvalidMaterial { material: material_index, reason };
+
+    let mut material = Material::default();
+    material.name = gltf_material.name.clone().unwrap_or_default();
+
+    if let Some(pbr) = &gltf_material.pbr_metallic_roughness {
+        if let Some(factor) = &pbr.base_color_factor {
+            if factor.len() != 4 {
+                return Err(invalid(format!("baseColorFactor has {} components", factor.len())));
+            }
+            material.base_color_factor = float4::new(factor[0], factor[1], factor[2], factor[3]);
+        }
+        material.metallic_factor = pbr.metallic_factor.unwrap_or(1.0);
+        material.roughness_factor = pbr.roughness_factor.unwrap_or(1.0);
+        material.base_color_texture = load_texture_ref(material_index, pbr.base_color_texture.as_ref(), file)?;
+        material.metallic_roughness_texture = load_texture_ref(material_index, pbr.metallic_roughness_texture.as_ref(), file)?;
+    }
+
+    material.normal_texture = load_texture_ref(material_index, gltf_material.normal_texture.as_ref(), file)?;
+    material.normal_scale = gltf_material.normal_texture.as_ref().and_then(|t| t.scale).unwrap_or(1.0);
+    material.occlusion_texture = load_texture_ref(material_index, gltf_material.occlusion_texture.as_ref(), file)?;
+    material.occlusion_strength = gltf_material.occlusion_texture.as_ref().and_then(|t| t.strength).unwrap_or(1.0);
+    material.emissive_texture = load_texture_ref(material_index, gltf_material.emissive_texture.as_ref(), file)?;
+    if let Some(factor) = &gltf_material.emissive_factor {
+        if factor.len() != 3 {
+            return Err(invalid(format!("emissiveFactor has {} components", factor.len())));
+        }
+        material.emissive_factor = float3::new(factor[0], factor[1], factor[2]);
+    }
+
+    material.alpha_mode = match gltf_material.alpha_mode.as_ref().map(|m| m.as_str()) {
+        None | Some("OPAQUE") => AlphaMode::Opaque,
+        Some("MASK") => AlphaMode::Mask,
+        Some("BLEND") => AlphaMode::Blend,
+        Some(mode) => return Err(invalid(format!("unknown alphaMode {}", mode)))
+    };
+    material.alpha_cutoff = gltf_material.alpha_cutoff.unwrap_or(0.5);
+    material.double_sided = gltf_material.double_sided;
+
+    debug!("loaded material {}: alpha_mode={:?}, double_sided={}, base_color_texture={:?}",
+           material.name, material.alpha_mode, material.double_sided, material.base_color_texture.map(|t| t.image));
+    return Ok(material);
+}
+
+/// encoded bytes of the image behind a material texture, resolved through its uri or buffer view
+pub fn load_texture_image(texture: &TextureRef, file: &GltfFile) -> Result<Vec<u8>, GltfError> {
+    return load_image_data(file, texture.image);
+}
+
+/// mime type of an image, from its mimeType or its uri extension
+pub fn image_mime_type(image_index: usize, file: &GltfFile) -> Option<String> {
+    let image = file.images.as_ref()?.get(image_index)?;
+    if image.mime_type.is_some() {
+        return image.mime_type.clone();
+    }
+    let uri = image.uri.as_ref()?;
+    if uri.starts_with("data:") {
+        return uri["data:".len()..].split(';').next().map(|m| m.to_owned());
+    }
+    let extension = uri.rsplit('.').next()?.to_lowercase();
+    return match extension.as_str() {
+        "png" => Some("image/png".to_owned()),
+        "jpg" | "jpeg" => Some("image/jpeg".to_owned()),
+        _ => None
+    };
+}
+
+/// resolve texture -> image and sampler, textures without a core image source are skipped
+fn load_texture_ref(material_index: usize, info: Option<&GltfTextureInfo>, file: &GltfFile) -> Result<Option<TextureRef>, GltfError> {
+    let info = match info {
+        Some(info) => info,
+        None => return Ok(None)
+    };
+    let invalid = |reason: String| GltfError::InvalidMaterial { material: material_index, reason };
+    let texture = file.textures.as_ref()
+        .and_then(|textures| textures.get(info.index))
+        .ok_or_else(|| invalid(format!("texture {} does not exist", info.index)))?;
+    let image = match texture.source {
+        Some(image) => image,
+        None => {
+            warn!("material {}: texture {} has no image source, ignored", material_index, info.index);
+            return Ok(None);
+        }
+    };
+    if file.images.as_ref().map_or(true, |images| image >= images.len()) {
+        return Err(invalid(format!("texture {} image {} does not exist", info.index, image)));
+    }
+
+    let mut sampler = TextureSampler { mag_filter: None, min_filter: None, wrap_s: GL_REPEAT, wrap_t: GL_REPEAT };
+    if let Some(sampler_index) = texture.sampler {
+        let gltf_sampler = file.samplers.as_ref()
+            .and_then(|samplers| samplers.get(sampler_index))
+            .ok_or_else(|| invalid(format!("texture {} sampler {} does not exist", info.index, sampler_index)))?;
+        sampler.mag_filter = gltf_sampler.mag_filter;
+        sampler.min_filter = gltf_sampler.min_filter;
+        sampler.wrap_s = gltf_sampler.wrap_s.unwrap_or(GL_REPEAT);
+        sampler.wrap_t = gltf_sampler.wrap_t.unwrap_or(GL_REPEAT);
+    }
+    return Ok(Some(TextureRef { image, sampler, tex_coord: info.tex_coord }));
+}
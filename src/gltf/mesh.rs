@@ -1,12 +1,109 @@
+use std::collections::HashMap;
+
 use byteorder::{LittleEndian, ReadBytesExt};
+use math::matrix::float4x4;
+use math::quaternion::quaternion;
 use math::vector::{float2, float3, float4, int4};
 
-use crate::gltf::{get_buffer_cursor, GltfComponentType, GltfFile};
-use crate::model::Mesh;
+use crate::anim::skeletal::cross;
+use crate::gltf::{get_accessor, matrix_from_gltf_node, read_accessor, read_component, GltfAccessor, GltfComponentType, GltfError, GltfFile, GltfMeshPrimitive};
+use crate::model::{Mesh, MeshGroup, MeshIndices, MorphTarget, PrimitiveMode};
 use crate::render::gl_geometry::VertexAttribs;
 
-pub fn load_mesh(name: &str, file: &GltfFile) -> Mesh {
-    info!("begin load mesh {}", name);
+/// load the first primitive of the named mesh
+/// prefer load_mesh_group for meshes with multiple primitives
+pub fn load_mesh(name: &str, file: &GltfFile) -> Result<Mesh, GltfError> {
+    let mut group = load_mesh_group(name, file)?;
+    if group.primitives.is_empty() {
+        return Err(GltfError::InvalidMesh { mesh: group.name, reason: "mesh has no primitives".to_owned() });
+    }
+    if group.primitives.len() > 1 {
+        warn!("load_mesh: mesh {} has {} primitives, only the first is used", group.name, group.primitives.len());
+    }
+    return Ok(group.primitives.swap_remove(0));
+}
+
+/// load every primitive of the named mesh
+pub fn load_mesh_group(name: &str, file: &GltfFile) -> Result<MeshGroup, GltfError> {
+    let mesh_index = find_mesh_index(name, file)?;
+    return load_mesh_group_at(mesh_index, file);
+}
+
+/// load every mesh in the file
+pub fn load_mesh_groups(file: &GltfFile) -> Result<Vec<MeshGroup>, GltfError> {
+    let mesh_count = file.meshes.as_ref().map_or(0, |meshes| meshes.len());
+    return (0..mesh_count).map(|mesh_index| load_mesh_group_at(mesh_index, file)).collect();
+}
+
+pub fn load_mesh_group_at(mesh_index: usize, file: &GltfFile) -> Result<MeshGroup, GltfError> {
+    let meshes = file.meshes.as_ref().filter(|meshes| !meshes.is_empty()).ok_or(GltfError::NoMeshes)?;
+    let mesh = meshes.get(mesh_index).ok_or_else(|| GltfError::InvalidMesh { mesh: format!("#{}", mesh_index),
+        reason: format!("mesh index out of range, the file has {} meshes", meshes.len()) })?;
+    info!("begin load mesh {} ({} primitives)", mesh.name, mesh.primitives.len());
+    let dequantization = dequantization_transform(mesh_index, file);
+    let primitives: Vec<Mesh> = mesh.primitives.iter().enumerate()
+        .map(|(primitive_index, primitive)| load_mesh_primitive(mesh_index, primitive_index, primitive, file))
+        .map(|primitive| primitive.map(|primitive| Mesh { dequantization, ..primitive }))
+        .collect::<Result<_, _>>()?;
+    return Ok(MeshGroup {
+        name: mesh.name.clone(),
+        primitives
+    });
+}
+
+/// find a mesh by mesh name, then by the name of a node instancing it
+fn find_mesh_index(name: &str, file: &GltfFile) -> Result<usize, GltfError> {
+    let meshes = file.meshes.as_ref().filter(|meshes| !meshes.is_empty()).ok_or(GltfError::NoMeshes)?;
+    if let Some(mesh_index) = meshes.iter().position(|m| m.name == name) {
+        return Ok(mesh_index);
+    }
+    let node_mesh = file.nodes.iter()
+        .find(|n| n.name == name && n.mesh.is_some())
+        .and_then(|n| n.mesh);
+    if let Some(mesh_index) = node_mesh {
+        return Ok(mesh_index);
+    }
+    return Err(GltfError::InvalidMesh { mesh: name.to_owned(), reason: "no mesh or node with this name".to_owned() });
+}
+
+/// KHR_mesh_quantization stores positions in integer space and moves the dequantization
+/// scale and offset into the transform of the node instancing the mesh.
+/// returns that node's local transform when a single node instances a quantized mesh, the vertices stay quantized
+fn dequantization_transform(mesh_index: usize, file: &GltfFile) -> Option<float4x4> {
+    if !file.uses_extension("KHR_mesh_quantization") {
+        return None;
+    }
+    let mesh = &file.meshes.as_ref().unwrap()[mesh_index];
+    let quantized = mesh.primitives.iter().any(|primitive| {
+        primitive.attributes.get("POSITION")
+            .and_then(|&accessor| file.accessors.get(accessor))
+            .map_or(false, |accessor| accessor.component_type != GltfComponentType::Float as i64)
+    });
+    if !quantized {
+        return None;
+    }
+    let mut instances = file.nodes.iter().filter(|node| node.mesh == Some(mesh_index));
+    let node = match instances.next() {
+        Some(node) => node,
+        None => {
+            warn!("quantized mesh {} is not instanced by a node, its positions stay quantized", mesh.name);
+            return None;
+        }
+    };
+    if instances.next().is_some() {
+        warn!("quantized mesh {} is instanced by multiple nodes, its positions stay quantized", mesh.name);
+        return None;
+    }
+    return Some(matrix_from_gltf_node(node));
+}
+
+fn load_mesh_primitive(mesh_index: usize, primitive_index: usize, mesh_primitive: &GltfMeshPrimitive, file: &GltfFile)
+    -> Result<Mesh, GltfError> {
+    // default mode is triangles
+    let gltf_mode = mesh_primitive.mode.unwrap_or(PrimitiveMode::Triangles as i64);
+    let mode = PrimitiveMode::from_gltf(gltf_mode)
+        .ok_or_else(|| GltfError::InvalidPrimitive { mesh: mesh_index, primitive: primitive_index,
+            reason: format!("unsupported primitive mode {}", gltf_mode) })?;
     let mut loaded_mesh: Mesh = Mesh {
         attribs: VertexAttribs {
             position: Vec::new(),
@@ -19,113 +116,257 @@ pub fn load_mesh(name: &str, file: &GltfFile) -> Mesh {
             joint_indices: Vec::new(),
             joint_weights: Vec::new()
         },
-        indices: Vec::new()
+        indices: MeshIndices::None,
+        mode,
+        material: mesh_primitive.material,
+        targets: Vec::new(),
+        weights: Vec::new(),
+        dequantization: None
     };
 
-    let meshes = file.meshes.as_ref().unwrap();
-    let mesh = &meshes[0];
-    let mesh_primitives = &mesh.primitives;
-    let mesh_primitive = &mesh_primitives[0];
-
     // load mesh indices
     if mesh_primitive.indices.is_some() {
-        let indices_accessor = &file.accessors[mesh_primitive.indices.unwrap()];
-        let indices_buffer_view = &file.buffer_views[indices_accessor.buffer_view];
-        if indices_accessor.accessor_type != "SCALAR" || indices_accessor.component_type != GltfComponentType::UnsignedShort as i64 {
-            panic!("unsupported accessor type for INDICES");
+        let indices_accessor_index = mesh_primitive.indices.unwrap();
+        let indices_accessor = get_accessor(file, indices_accessor_index)?;
+        if indices_accessor.accessor_type != "SCALAR" {
+            return Err(unsupported_accessor("INDICES", indices_accessor_index, indices_accessor));
         }
 
-        let mut cursor = get_buffer_cursor(&file, &indices_buffer_view);
-        for i in 0..indices_accessor.count {
-            let index = cursor.read_u16::<LittleEndian>().unwrap();
-            loaded_mesh.indices.push(index);
-        }
+        loaded_mesh.indices = match indices_accessor.component_type {
+            t if t == GltfComponentType::UnsignedByte as i64 => MeshIndices::U8(
+                read_accessor(file, indices_accessor_index, |cursor| cursor.read_u8().unwrap())?),
+            t if t == GltfComponentType::UnsignedShort as i64 => MeshIndices::U16(
+                read_accessor(file, indices_accessor_index, |cursor| cursor.read_u16::<LittleEndian>().unwrap())?),
+            t if t == GltfComponentType::UnsignedInt as i64 => MeshIndices::U32(
+                read_accessor(file, indices_accessor_index, |cursor| cursor.read_u32::<LittleEndian>().unwrap())?),
+            _ => return Err(unsupported_accessor("INDICES", indices_accessor_index, indices_accessor))
+        };
     }
 
+    // tangent handedness (w), resolved into binormals once normals are loaded
+    let mut tangent_signs: Vec<f32> = Vec::new();
+
+    // KHR_mesh_quantization widens the component types allowed for positions, normals, tangents and uvs
+    let quantization = file.uses_extension("KHR_mesh_quantization");
+
     // load mesh attributes
     for (attr_key, attr_val) in &mesh_primitive.attributes {
         let accessor_index = *attr_val;
+        let accessor = get_accessor(file, accessor_index)?;
         if attr_key == "POSITION" {
-            let position_accessor = &file.accessors[accessor_index];
-            let position_buffer_view = &file.buffer_views[position_accessor.buffer_view];
-            if position_accessor.accessor_type != "VEC3" || position_accessor.component_type != GltfComponentType::Float as i64 {
-                panic!("unsupported accessor type for POSITION");
+            if accessor.accessor_type != "VEC3" || !is_valid_component_type(attr_key, accessor, quantization) {
+                return Err(unsupported_accessor(attr_key, accessor_index, accessor));
             }
 
-            let mut cursor = get_buffer_cursor(&file, &position_buffer_view);
-            for _ in 0..position_accessor.count {
-                let x = cursor.read_f32::<LittleEndian>().unwrap();
-                let y = cursor.read_f32::<LittleEndian>().unwrap();
-                let z = cursor.read_f32::<LittleEndian>().unwrap();
-                let vertex = float3::new(x, y, z);
-                loaded_mesh.attribs.position.push(vertex);
+            let (component_type, normalized) = (accessor.component_type, accessor.normalized);
+            loaded_mesh.attribs.position = read_accessor(file, accessor_index, |cursor| {
+                let x = read_component(cursor, component_type, normalized);
+                let y = read_component(cursor, component_type, normalized);
+                let z = read_component(cursor, component_type, normalized);
+                float3::new(x, y, z)
+            })?;
+        } else if attr_key == "TEXCOORD_0" || attr_key == "TEXCOORD_1" {
+            if accessor.accessor_type != "VEC2" || !is_valid_component_type(attr_key, accessor, quantization) {
+                return Err(unsupported_accessor(attr_key, accessor_index, accessor));
             }
-        } else if attr_key == "TEXCOORD_0" {
-            let uv_accessor = &file.accessors[accessor_index];
-            let uv_buffer_view = &file.buffer_views[uv_accessor.buffer_view];
-            if uv_accessor.accessor_type != "VEC2" || uv_accessor.component_type != GltfComponentType::Float as i64 {
-                panic!("unsupported accessor type for TEXCOORD_0");
+
+            let (component_type, normalized) = (accessor.component_type, accessor.normalized);
+            let uvs = read_accessor(file, accessor_index, |cursor| {
+                let x = read_component(cursor, component_type, normalized);
+                let y = read_component(cursor, component_type, normalized);
+                float2::new(x, y)
+            })?;
+            if attr_key == "TEXCOORD_0" {
+                loaded_mesh.attribs.uv0 = uvs;
+            } else {
+                loaded_mesh.attribs.uv1 = uvs;
+            }
+        } else if attr_key == "NORMAL" {
+            if accessor.accessor_type != "VEC3" || !is_valid_component_type(attr_key, accessor, quantization) {
+                return Err(unsupported_accessor(attr_key, accessor_index, accessor));
+            }
+
+            let (component_type, normalized) = (accessor.component_type, accessor.normalized);
+            loaded_mesh.attribs.normal = read_accessor(file, accessor_index, |cursor| {
+                let x = read_component(cursor, component_type, normalized);
+                let y = read_component(cursor, component_type, normalized);
+                let z = read_component(cursor, component_type, normalized);
+                float3::new(x, y, z)
+            })?;
+        } else if attr_key == "TANGENT" {
+            if accessor.accessor_type != "VEC4" || !is_valid_component_type(attr_key, accessor, quantization) {
+                return Err(unsupported_accessor(attr_key, accessor_index, accessor));
             }
 
-            let mut cursor = get_buffer_cursor(&file, &uv_buffer_view);
-            for _ in 0..uv_accessor.count {
-                let x = cursor.read_f32::<LittleEndian>().unwrap();
-                let y = cursor.read_f32::<LittleEndian>().unwrap();
-                loaded_mesh.attribs.uv0.push(float2::new(x, y));
+            let (component_type, normalized) = (accessor.component_type, accessor.normalized);
+            let tangents = read_accessor(file, accessor_index, |cursor| {
+                let x = read_component(cursor, component_type, normalized);
+                let y = read_component(cursor, component_type, normalized);
+                let z = read_component(cursor, component_type, normalized);
+                let w = read_component(cursor, component_type, normalized);
+                (float3::new(x, y, z), w)
+            })?;
+            loaded_mesh.attribs.tangent = tangents.iter().map(|t| t.0).collect();
+            tangent_signs = tangents.iter().map(|t| t.1).collect();
+        } else if attr_key == "COLOR_0" {
+            let components = match accessor.accessor_type.as_str() {
+                "VEC3" => 3,
+                "VEC4" => 4,
+                _ => return Err(unsupported_accessor(attr_key, accessor_index, accessor))
+            };
+            if !is_valid_component_type(attr_key, accessor, quantization) {
+                return Err(unsupported_accessor(attr_key, accessor_index, accessor));
             }
+
+            let (component_type, normalized) = (accessor.component_type, accessor.normalized);
+            loaded_mesh.attribs.color = read_accessor(file, accessor_index, |cursor| {
+                // rgb colors are opaque
+                let mut rgba = [1.0f32; 4];
+                for c in 0..components {
+                    rgba[c] = read_component(cursor, component_type, normalized);
+                }
+                float4::new(rgba[0], rgba[1], rgba[2], rgba[3])
+            })?;
         } else if attr_key == "JOINTS_0" {
-            let joints0_accessor_index = *mesh_primitive.attributes.get("JOINTS_0").unwrap();
-            let joints0_accessor = &file.accessors[joints0_accessor_index];
-            if joints0_accessor.accessor_type != "VEC4" {
-                panic!("unsupported accessor type for JOINTS_0");
+            if accessor.accessor_type != "VEC4" {
+                return Err(unsupported_accessor(attr_key, accessor_index, accessor));
+            }
+            let component_type = accessor.component_type;
+            if component_type != GltfComponentType::UnsignedByte as i64
+                && component_type != GltfComponentType::UnsignedShort as i64 {
+                return Err(unsupported_accessor(attr_key, accessor_index, accessor));
             }
-            let joints0_buffer_view = &file.buffer_views[joints0_accessor.buffer_view];
-            let mut cursor = get_buffer_cursor(&file, &joints0_buffer_view);
-            for _ in 0..joints0_accessor.count {
-                if joints0_accessor.component_type == GltfComponentType::UnsignedByte as i64 {
+
+            loaded_mesh.attribs.joint_indices = read_accessor(file, accessor_index, |cursor| {
+                if component_type == GltfComponentType::UnsignedByte as i64 {
                     let b1 = cursor.read_u8().unwrap() as i32;
                     let b2 = cursor.read_u8().unwrap() as i32;
                     let b3 = cursor.read_u8().unwrap() as i32;
                     let b4 = cursor.read_u8().unwrap() as i32;
-                    loaded_mesh.attribs.joint_indices.push(int4::new(b1, b2, b3, b4));
-                } else if joints0_accessor.component_type == GltfComponentType::UnsignedShort as i64 {
+                    int4::new(b1, b2, b3, b4)
+                } else {
                     let b1 = cursor.read_u16::<LittleEndian>().unwrap() as i32;
                     let b2 = cursor.read_u16::<LittleEndian>().unwrap() as i32;
                     let b3 = cursor.read_u16::<LittleEndian>().unwrap() as i32;
                     let b4 = cursor.read_u16::<LittleEndian>().unwrap() as i32;
-                    loaded_mesh.attribs.joint_indices.push(int4::new(b1, b2, b3, b4));
-                } else {
-                    panic!("unsupported component type for JOINTS_0")
+                    int4::new(b1, b2, b3, b4)
                 }
-            }
+            })?;
         } else if attr_key == "WEIGHTS_0" {
-            let weights0_accessor_index = *mesh_primitive.attributes.get("WEIGHTS_0").unwrap();
-            let weights0_accessor = &file.accessors[weights0_accessor_index];
-            if weights0_accessor.accessor_type != "VEC4" {
-                panic!("unsupported accessor type for WEIGHTS_0");
+            if accessor.accessor_type != "VEC4" {
+                return Err(unsupported_accessor(attr_key, accessor_index, accessor));
             }
-            let weights0_buffer_view = &file.buffer_views[weights0_accessor.buffer_view];
-            let mut cursor = get_buffer_cursor(&file, &weights0_buffer_view);
-            for _ in 0..weights0_accessor.count {
-                if weights0_accessor.component_type == GltfComponentType::Float as i64 {
-                    let w1 = cursor.read_f32::<LittleEndian>().unwrap();
-                    let w2 = cursor.read_f32::<LittleEndian>().unwrap();
-                    let w3 = cursor.read_f32::<LittleEndian>().unwrap();
-                    let w4 = cursor.read_f32::<LittleEndian>().unwrap();
-                    loaded_mesh.attribs.joint_weights.push(float4::new(w1, w2, w3, w4));
-                } else {
-                    // can also be normalized u8 or normalized u16
-                    panic!("yet unsupported WEIGHTS_0 component type");
-                }
+            if !is_valid_component_type(attr_key, accessor, quantization) {
+                return Err(unsupported_accessor(attr_key, accessor_index, accessor));
+            }
+
+            // quantized weights are expanded to floats, the vertex layout packs weights as float4
+            let (component_type, normalized) = (accessor.component_type, accessor.normalized);
+            loaded_mesh.attribs.joint_weights = read_accessor(file, accessor_index, |cursor| {
+                let w1 = read_component(cursor, component_type, normalized);
+                let w2 = read_component(cursor, component_type, normalized);
+                let w3 = read_component(cursor, component_type, normalized);
+                let w4 = read_component(cursor, component_type, normalized);
+                float4::new(w1, w2, w3, w4)
+            })?;
+        } else {
+            warn!("load_mesh: ignoring unsupported vertex attribute {}", attr_key);
+        }
+    }
+
+    // binormal = cross(normal, tangent) * handedness
+    if loaded_mesh.attribs.tangent.len() > 0 {
+        if loaded_mesh.attribs.normal.len() == loaded_mesh.attribs.tangent.len() {
+            for i in 0..loaded_mesh.attribs.tangent.len() {
+                let binormal = cross(&loaded_mesh.attribs.normal[i], &loaded_mesh.attribs.tangent[i]) * tangent_signs[i];
+                loaded_mesh.attribs.binormal.push(binormal);
+            }
+        } else {
+            warn!("load_mesh: tangents without matching normals, binormals not generated");
+        }
+    }
+
+    // morph targets, weights default to zero when the mesh does not provide them
+    if let Some(targets) = &mesh_primitive.targets {
+        for target in targets.iter() {
+            let morph_target = load_morph_target(target, file, quantization)?;
+            let vertex_count = loaded_mesh.attribs.position.len();
+            if [&morph_target.position, &morph_target.normal, &morph_target.tangent].iter()
+                .any(|deltas| !deltas.is_empty() && deltas.len() != vertex_count) {
+                return Err(GltfError::InvalidPrimitive { mesh: mesh_index, primitive: primitive_index,
+                    reason: format!("morph target {} vertex count does not match {} positions", loaded_mesh.targets.len(), vertex_count) });
             }
+            loaded_mesh.targets.push(morph_target);
         }
+        let mesh_weights = file.meshes.as_ref().unwrap()[mesh_index].weights.as_ref();
+        loaded_mesh.weights = match mesh_weights {
+            Some(weights) if weights.len() == targets.len() => weights.clone(),
+            Some(weights) => return Err(GltfError::InvalidPrimitive { mesh: mesh_index, primitive: primitive_index,
+                reason: format!("{} mesh weights for {} morph targets", weights.len(), targets.len()) }),
+            None => vec![0.0; targets.len()]
+        };
     }
 
-    info!("finished loading mesh: positions={}, indices={}, uvs={}, colors={}, normals={},\
-                joint_indices={}, joint_weights={}",
-          loaded_mesh.attribs.position.len(), loaded_mesh.indices.len(), loaded_mesh.attribs.uv0.len(),
-          loaded_mesh.attribs.color.len(), loaded_mesh.attribs.normal.len(), loaded_mesh.attribs.joint_indices.len(),
+    info!("finished loading mesh primitive: mode={:?}, positions={}, indices={}, uvs={}, uv1s={}, colors={}, normals={},\
+                tangents={}, joint_indices={}, joint_weights={}",
+          loaded_mesh.mode, loaded_mesh.attribs.position.len(), loaded_mesh.indices.len(), loaded_mesh.attribs.uv0.len(),
+          loaded_mesh.attribs.uv1.len(), loaded_mesh.attribs.color.len(), loaded_mesh.attribs.normal.len(),
+          loaded_mesh.attribs.tangent.len(), loaded_mesh.attribs.joint_indices.len(),
           loaded_mesh.attribs.joint_weights.len());
 
-    return loaded_mesh;
-}
\ No newline at end of file
+    return Ok(loaded_mesh);
+}
+
+/// load the position, normal and tangent deltas of a morph target
+fn load_morph_target(target: &HashMap<String, usize>, file: &GltfFile, quantization: bool) -> Result<MorphTarget, GltfError> {
+    let mut morph_target = MorphTarget { position: Vec::new(), normal: Vec::new(), tangent: Vec::new() };
+    for (attr_key, attr_val) in target {
+        let accessor_index = *attr_val;
+        let accessor = get_accessor(file, accessor_index)?;
+        if attr_key != "POSITION" && attr_key != "NORMAL" && attr_key != "TANGENT" {
+            warn!("load_mesh: ignoring unsupported morph target attribute {}", attr_key);
+            continue;
+        }
+        // target tangents are vec3, the handedness is not morphed
+        if accessor.accessor_type != "VEC3" || !is_valid_component_type(attr_key, accessor, quantization) {
+            return Err(unsupported_accessor(attr_key, accessor_index, accessor));
+        }
+
+        let (component_type, normalized) = (accessor.component_type, accessor.normalized);
+        let deltas = read_accessor(file, accessor_index, |cursor| {
+            let x = read_component(cursor, component_type, normalized);
+            let y = read_component(cursor, component_type, normalized);
+            let z = read_component(cursor, component_type, normalized);
+            float3::new(x, y, z)
+        })?;
+        match attr_key.as_str() {
+            "POSITION" => morph_target.position = deltas,
+            "NORMAL" => morph_target.normal = deltas,
+            _ => morph_target.tangent = deltas
+        }
+    }
+    return Ok(morph_target);
+}
+
+fn unsupported_accessor(attr_key: &str, accessor_index: usize, accessor: &GltfAccessor) -> GltfError {
+    return GltfError::InvalidAccessor { accessor: accessor_index,
+        reason: format!("unsupported {} with component type {} for {}", accessor.accessor_type, accessor.component_type, attr_key) };
+}
+
+/// whether an attribute accessor's component type is allowed by the gltf spec,
+/// or by KHR_mesh_quantization when quantization is enabled
+fn is_valid_component_type(attr_key: &str, accessor: &GltfAccessor, quantization: bool) -> bool {
+    let component_type = accessor.component_type;
+    if component_type == GltfComponentType::Float as i64 {
+        return true;
+    }
+    let signed = component_type == GltfComponentType::Byte as i64 || component_type == GltfComponentType::Short as i64;
+    let unsigned = component_type == GltfComponentType::UnsignedByte as i64
+        || component_type == GltfComponentType::UnsignedShort as i64;
+    return match attr_key {
+        "POSITION" => quantization && (signed || unsigned),
+        "NORMAL" | "TANGENT" => quantization && signed && accessor.normalized,
+        "TEXCOORD_0" | "TEXCOORD_1" => (unsigned && accessor.normalized) || (quantization && (signed || unsigned)),
+        _ => unsigned && accessor.normalized
+    };
+}
@@ -0,0 +1,484 @@
+//! EXT_meshopt_compression buffer view decoding
+//! see https://github.com/KhronosGroup/glTF/tree/master/extensions/2.0/Vendor/EXT_meshopt_compression
+
+const VERTEX_HEADER: u8 = 0xa0;
+const TRIANGLE_HEADER: u8 = 0xe0;
+const SEQUENCE_HEADER: u8 = 0xd0;
+
+const BYTE_GROUP_SIZE: usize = 16;
+const VERTEX_BLOCK_SIZE_BYTES: usize = 8192;
+const VERTEX_BLOCK_MAX_SIZE: usize = 256;
+const VERTEX_TAIL_MAX_SIZE: usize = 32;
+const TRIANGLE_CODEAUX_SIZE: usize = 16;
+const SEQUENCE_TAIL_SIZE: usize = 4;
+
+/// decode a compressed buffer view into count * stride bytes, at most max_size
+/// mode is one of ATTRIBUTES, TRIANGLES or INDICES, filter one of NONE, OCTAHEDRAL, QUATERNION or EXPONENTIAL
+pub fn decode_buffer_view(mode: &str, filter: &str, count: usize, stride: usize, max_size: usize, data: &[u8])
+    -> Result<Vec<u8>, &'static str> {
+    let mut decoded = match mode {
+        "ATTRIBUTES" => decode_vertex_buffer(count, stride, max_size, data)?,
+        "TRIANGLES" => decode_index_buffer(count, stride, max_size, data)?,
+        "INDICES" => decode_index_sequence(count, stride, max_size, data)?,
+        _ => return Err("unknown compression mode")
+    };
+    if filter != "NONE" {
+        if mode != "ATTRIBUTES" {
+            return Err("filters are only valid for attributes");
+        }
+        apply_filter(filter, &mut decoded, count, stride)?;
+    }
+    return Ok(decoded);
+}
+
+/// size in bytes of count decoded elements, checked before allocating since count comes from the file
+fn output_size(count: usize, element_size: usize, max_size: usize) -> Result<usize, &'static str> {
+    let size = count.checked_mul(element_size).ok_or("decoded size overflows")?;
+    if size > max_size {
+        return Err("decoded size exceeds the buffer view length");
+    }
+    return Ok(size);
+}
+
+/// vertex codec: per vertex byte channels, delta + zigzag encoded against the previous vertex,
+/// stored in blocks of 16 byte groups with 0, 2, 4 or 8 bits per value
+pub fn decode_vertex_buffer(count: usize, vertex_size: usize, max_size: usize, data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    if vertex_size == 0 || vertex_size > 256 || vertex_size % 4 != 0 {
+        return Err("invalid vertex size");
+    }
+    let output_size = output_size(count, vertex_size, max_size)?;
+    if data.is_empty() || data[0] & 0xf0 != VERTEX_HEADER {
+        return Err("invalid vertex buffer header");
+    }
+    if data[0] & 0x0f != 0 {
+        return Err("unsupported vertex codec version");
+    }
+    let tail_size = vertex_size.max(VERTEX_TAIL_MAX_SIZE);
+    if data.len() < 1 + tail_size {
+        return Err("truncated vertex buffer");
+    }
+
+    // the first vertex is stored at the end of the buffer and seeds the delta chain
+    let mut last_vertex: Vec<u8> = data[data.len() - vertex_size..].to_vec();
+    let block_size = vertex_block_size(vertex_size);
+    let mut output = vec![0u8; output_size];
+    let mut buffer = [0u8; VERTEX_BLOCK_MAX_SIZE];
+    let mut pos = 1;
+    let mut vertex_offset = 0;
+    while vertex_offset < count {
+        let block_count = block_size.min(count - vertex_offset);
+        let aligned_count = (block_count + BYTE_GROUP_SIZE - 1) & !(BYTE_GROUP_SIZE - 1);
+        for k in 0..vertex_size {
+            pos = decode_bytes(data, pos, &mut buffer[..aligned_count])?;
+            let mut p = last_vertex[k];
+            for i in 0..block_count {
+                p = p.wrapping_add(unzigzag8(buffer[i]));
+                output[(vertex_offset + i) * vertex_size + k] = p;
+            }
+            last_vertex[k] = p;
+        }
+        vertex_offset += block_count;
+    }
+
+    if data.len() - pos != tail_size {
+        return Err("vertex buffer size mismatch");
+    }
+    return Ok(output);
+}
+
+fn vertex_block_size(vertex_size: usize) -> usize {
+    let block_size = (VERTEX_BLOCK_SIZE_BYTES / vertex_size) & !(BYTE_GROUP_SIZE - 1);
+    return block_size.min(VERTEX_BLOCK_MAX_SIZE);
+}
+
+/// decode one byte channel of a vertex block, returns the new read position
+fn decode_bytes(data: &[u8], pos: usize, buffer: &mut [u8]) -> Result<usize, &'static str> {
+    let group_count = buffer.len() / BYTE_GROUP_SIZE;
+    let header_size = (group_count + 3) / 4;
+    if pos + header_size > data.len() {
+        return Err("truncated vertex block header");
+    }
+    let header = &data[pos..pos + header_size];
+    let mut pos = pos + header_size;
+    for group in 0..group_count {
+        let bits = (header[group / 4] >> ((group % 4) * 2)) & 3;
+        let group_buffer = &mut buffer[group * BYTE_GROUP_SIZE..(group + 1) * BYTE_GROUP_SIZE];
+        pos = decode_bytes_group(data, pos, group_buffer, bits)?;
+    }
+    return Ok(pos);
+}
+
+/// bits: 0 = all zero, 1 = 2 bit values, 2 = 4 bit values, 3 = raw bytes.
+/// packed values equal to the all-ones sentinel are followed up by a full byte after the group
+fn decode_bytes_group(data: &[u8], pos: usize, group: &mut [u8], bits: u8) -> Result<usize, &'static str> {
+    if bits == 0 {
+        for value in group.iter_mut() {
+            *value = 0;
+        }
+        return Ok(pos);
+    }
+    if bits == 3 {
+        if pos + BYTE_GROUP_SIZE > data.len() {
+            return Err("truncated vertex byte group");
+        }
+        group.copy_from_slice(&data[pos..pos + BYTE_GROUP_SIZE]);
+        return Ok(pos + BYTE_GROUP_SIZE);
+    }
+
+    let value_bits: usize = if bits == 1 { 2 } else { 4 };
+    let values_per_byte = 8 / value_bits;
+    let packed_size = BYTE_GROUP_SIZE / values_per_byte;
+    let sentinel = ((1u16 << value_bits) - 1) as u8;
+    if pos + packed_size > data.len() {
+        return Err("truncated vertex byte group");
+    }
+    let mut extra = pos + packed_size;
+    for i in 0..BYTE_GROUP_SIZE {
+        let byte = data[pos + i / values_per_byte];
+        let shift = 8 - value_bits - (i % values_per_byte) * value_bits;
+        let value = (byte >> shift) & sentinel;
+        group[i] = if value == sentinel {
+            let value = *data.get(extra).ok_or("truncated vertex byte group")?;
+            extra += 1;
+            value
+        } else {
+            value
+        };
+    }
+    return Ok(extra);
+}
+
+fn unzigzag8(v: u8) -> u8 {
+    return (0u8.wrapping_sub(v & 1)) ^ (v >> 1);
+}
+
+/// index codec: triangle list encoded against a fifo of recent edges and vertices
+pub fn decode_index_buffer(count: usize, index_size: usize, max_size: usize, data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    if count % 3 != 0 {
+        return Err("index count is not a multiple of 3");
+    }
+    if index_size != 2 && index_size != 4 {
+        return Err("invalid index size");
+    }
+    let output_size = output_size(count, index_size, max_size)?;
+    if data.len() < 1 + count / 3 + TRIANGLE_CODEAUX_SIZE {
+        return Err("truncated index buffer");
+    }
+    if data[0] & 0xf0 != TRIANGLE_HEADER {
+        return Err("invalid index buffer header");
+    }
+    let version = data[0] & 0x0f;
+    if version > 1 {
+        return Err("unsupported index codec version");
+    }
+
+    let mut edge_fifo = [[u32::MAX; 2]; 16];
+    let mut vertex_fifo = [u32::MAX; 16];
+    let mut edge_fifo_offset = 0usize;
+    let mut vertex_fifo_offset = 0usize;
+    let mut next = 0u32;
+    let mut last = 0u32;
+    let fec_max = if version >= 1 { 13 } else { 15 };
+
+    let codes = &data[1..1 + count / 3];
+    let data_safe_end = data.len() - TRIANGLE_CODEAUX_SIZE;
+    let codeaux_table = &data[data_safe_end..];
+    let mut pos = 1 + count / 3;
+
+    fn push_edge(fifo: &mut [[u32; 2]; 16], offset: &mut usize, a: u32, b: u32) {
+        fifo[*offset] = [a, b];
+        *offset = (*offset + 1) & 15;
+    }
+    fn push_vertex(fifo: &mut [u32; 16], offset: &mut usize, v: u32, cond: bool) {
+        fifo[*offset] = v;
+        *offset = (*offset + cond as usize) & 15;
+    }
+
+    let mut output: Vec<u8> = Vec::with_capacity(output_size);
+    for &codetri in codes.iter() {
+        let (a, b, c);
+        if codetri < 0xf0 {
+            let fe = (codetri >> 4) as usize;
+            let edge = edge_fifo[edge_fifo_offset.wrapping_sub(1 + fe) & 15];
+            a = edge[0];
+            b = edge[1];
+            let fec = (codetri & 15) as usize;
+            if fec < fec_max {
+                let cf = vertex_fifo[vertex_fifo_offset.wrapping_sub(1 + fec) & 15];
+                c = if fec == 0 { next } else { cf };
+                if fec == 0 {
+                    next += 1;
+                }
+                push_vertex(&mut vertex_fifo, &mut vertex_fifo_offset, c, fec == 0);
+            } else {
+                // 13 and 14 encode -1 and +1 relative to the last free index
+                c = if fec != 15 {
+                    last.wrapping_add(if fec == 13 { u32::MAX } else { 1 })
+                } else {
+                    decode_index(data, &mut pos, data_safe_end, last)?
+                };
+                last = c;
+                push_vertex(&mut vertex_fifo, &mut vertex_fifo_offset, c, true);
+            }
+            push_edge(&mut edge_fifo, &mut edge_fifo_offset, c, b);
+            push_edge(&mut edge_fifo, &mut edge_fifo_offset, a, c);
+        } else if codetri < 0xfe {
+            // fast path: codeaux from the table
+            let codeaux = codeaux_table[(codetri & 15) as usize];
+            let feb = (codeaux >> 4) as usize;
+            let fec = (codeaux & 15) as usize;
+
+            a = next;
+            next += 1;
+            let bf = vertex_fifo[vertex_fifo_offset.wrapping_sub(feb) & 15];
+            b = if feb == 0 { next } else { bf };
+            if feb == 0 {
+                next += 1;
+            }
+            let cf = vertex_fifo[vertex_fifo_offset.wrapping_sub(fec) & 15];
+            c = if fec == 0 { next } else { cf };
+            if fec == 0 {
+                next += 1;
+            }
+
+            push_vertex(&mut vertex_fifo, &mut vertex_fifo_offset, a, true);
+            push_vertex(&mut vertex_fifo, &mut vertex_fifo_offset, b, feb == 0);
+            push_vertex(&mut vertex_fifo, &mut vertex_fifo_offset, c, fec == 0);
+            push_edge(&mut edge_fifo, &mut edge_fifo_offset, b, a);
+            push_edge(&mut edge_fifo, &mut edge_fifo_offset, c, b);
+            push_edge(&mut edge_fifo, &mut edge_fifo_offset, a, c);
+        } else {
+            // slow path: codeaux as a full byte
+            if pos >= data_safe_end {
+                return Err("truncated index buffer");
+            }
+            let codeaux = data[pos];
+            pos += 1;
+            let fea = if codetri == 0xfe { 0 } else { 15 };
+            let feb = (codeaux >> 4) as usize;
+            let fec = (codeaux & 15) as usize;
+
+            // reset: codeaux is 0 but not encoded through the table
+            if codeaux == 0 {
+                next = 0;
+            }
+
+            let mut va = if fea == 0 { next += 1; next - 1 } else { 0 };
+            let mut vb = if feb == 0 { next += 1; next - 1 } else { vertex_fifo[vertex_fifo_offset.wrapping_sub(feb) & 15] };
+            let mut vc = if fec == 0 { next += 1; next - 1 } else { vertex_fifo[vertex_fifo_offset.wrapping_sub(fec) & 15] };
+
+            if fea == 15 {
+                va = decode_index(data, &mut pos, data_safe_end, last)?;
+                last = va;
+            }
+            if feb == 15 {
+                vb = decode_index(data, &mut pos, data_safe_end, last)?;
+                last = vb;
+            }
+            if fec == 15 {
+                vc = decode_index(data, &mut pos, data_safe_end, last)?;
+                last = vc;
+            }
+            a = va;
+            b = vb;
+            c = vc;
+
+            push_vertex(&mut vertex_fifo, &mut vertex_fifo_offset, a, true);
+            push_vertex(&mut vertex_fifo, &mut vertex_fifo_offset, b, feb == 0 || feb == 15);
+            push_vertex(&mut vertex_fifo, &mut vertex_fifo_offset, c, fec == 0 || fec == 15);
+            push_edge(&mut edge_fifo, &mut edge_fifo_offset, b, a);
+            push_edge(&mut edge_fifo, &mut edge_fifo_offset, c, b);
+            push_edge(&mut edge_fifo, &mut edge_fifo_offset, a, c);
+        }
+        write_index(&mut output, index_size, a);
+        write_index(&mut output, index_size, b);
+        write_index(&mut output, index_size, c);
+    }
+
+    if pos != data_safe_end {
+        return Err("index buffer size mismatch");
+    }
+    return Ok(output);
+}
+
+/// index sequence codec: zigzag deltas against one of two running baselines
+pub fn decode_index_sequence(count: usize, index_size: usize, max_size: usize, data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    if index_size != 2 && index_size != 4 {
+        return Err("invalid index size");
+    }
+    let output_size = output_size(count, index_size, max_size)?;
+    if data.len() < 1 + count + SEQUENCE_TAIL_SIZE {
+        return Err("truncated index sequence");
+    }
+    if data[0] & 0xf0 != SEQUENCE_HEADER {
+        return Err("invalid index sequence header");
+    }
+    if data[0] & 0x0f > 1 {
+        return Err("unsupported index sequence version");
+    }
+
+    let data_safe_end = data.len() - SEQUENCE_TAIL_SIZE;
+    let mut last = [0u32; 2];
+    let mut pos = 1;
+    let mut output: Vec<u8> = Vec::with_capacity(output_size);
+    for _ in 0..count {
+        let v = decode_vbyte(data, &mut pos, data_safe_end)?;
+        let current = (v & 1) as usize;
+        let v = v >> 1;
+        let delta = (v >> 1) ^ 0u32.wrapping_sub(v & 1);
+        let index = last[current].wrapping_add(delta);
+        last[current] = index;
+        write_index(&mut output, index_size, index);
+    }
+
+    if pos != data_safe_end {
+        return Err("index sequence size mismatch");
+    }
+    return Ok(output);
+}
+
+fn decode_vbyte(data: &[u8], pos: &mut usize, end: usize) -> Result<u32, &'static str> {
+    let mut result = 0u32;
+    let mut shift = 0;
+    loop {
+        if *pos >= end {
+            return Err("truncated varint");
+        }
+        let byte = data[*pos];
+        *pos += 1;
+        result |= ((byte & 127) as u32).wrapping_shl(shift);
+        if byte < 128 || shift >= 28 {
+            return Ok(result);
+        }
+        shift += 7;
+    }
+}
+
+fn decode_index(data: &[u8], pos: &mut usize, end: usize, last: u32) -> Result<u32, &'static str> {
+    let v = decode_vbyte(data, pos, end)?;
+    let delta = (v >> 1) ^ 0u32.wrapping_sub(v & 1);
+    return Ok(last.wrapping_add(delta));
+}
+
+fn write_index(output: &mut Vec<u8>, index_size: usize, index: u32) {
+    if index_size == 2 {
+        output.extend_from_slice(&(index as u16).to_le_bytes());
+    } else {
+        output.extend_from_slice(&index.to_le_bytes());
+    }
+}
+
+/// filters reconstruct attribute data in place after decoding
+pub fn apply_filter(filter: &str, data: &mut [u8], count: usize, stride: usize) -> Result<(), &'static str> {
+    match filter {
+        "OCTAHEDRAL" => {
+            if stride != 4 && stride != 8 {
+                return Err("octahedral filter requires a stride of 4 or 8");
+            }
+            let component_size = stride / 4;
+            let max = ((1i32 << (component_size * 8 - 1)) - 1) as f32;
+            for i in 0..count {
+                let element = &mut data[i * stride..(i + 1) * stride];
+                let mut x = read_signed(element, 0, component_size) as f32;
+                let mut y = read_signed(element, 1, component_size) as f32;
+                // z is stored as 1.0 at the same bit count
+                let z = read_signed(element, 2, component_size) as f32 - x.abs() - y.abs();
+
+                // fixup octahedral coordinates for z < 0
+                let t = if z >= 0.0 { 0.0 } else { z };
+                x += if x >= 0.0 { t } else { -t };
+                y += if y >= 0.0 { t } else { -t };
+
+                let l = (x * x + y * y + z * z).sqrt();
+                let s = max / l;
+                write_signed(element, 0, component_size, round_signed(x * s));
+                write_signed(element, 1, component_size, round_signed(y * s));
+                write_signed(element, 2, component_size, round_signed(z * s));
+            }
+        },
+        "QUATERNION" => {
+            if stride != 8 {
+                return Err("quaternion filter requires a stride of 8");
+            }
+            let scale = 1.0 / 2.0f32.sqrt();
+            for i in 0..count {
+                let element = &mut data[i * stride..(i + 1) * stride];
+                let packed = read_signed(element, 3, 2);
+                // the scale is stored in the high bits of the last component
+                let ss = scale / (packed | 3) as f32;
+                let x = read_signed(element, 0, 2) as f32 * ss;
+                let y = read_signed(element, 1, 2) as f32 * ss;
+                let z = read_signed(element, 2, 2) as f32 * ss;
+                // reconstruct w, clamped to avoid NaN from precision errors
+                let ww = 1.0 - x * x - y * y - z * z;
+                let w = if ww >= 0.0 { ww } else { 0.0 }.sqrt();
+
+                // the index of the largest component (w) is stored in the low bits
+                let qc = (packed & 3) as usize;
+                write_signed(element, (qc + 1) & 3, 2, round_signed(x * 32767.0));
+                write_signed(element, (qc + 2) & 3, 2, round_signed(y * 32767.0));
+                write_signed(element, (qc + 3) & 3, 2, round_signed(z * 32767.0));
+                write_signed(element, qc & 3, 2, round_signed(w * 32767.0));
+            }
+        },
+        "EXPONENTIAL" => {
+            if stride % 4 != 0 {
+                return Err("exponential filter requires a stride divisible by 4");
+            }
+            for i in 0..count * stride / 4 {
+                let bytes = &mut data[i * 4..(i + 1) * 4];
+                let v = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                // 24 bit signed mantissa, 8 bit signed exponent
+                let mantissa = ((v << 8) as i32) >> 8;
+                let exponent = (v as i32) >> 24;
+                let value = f32::from_bits(((exponent + 127) as u32) << 23) * mantissa as f32;
+                bytes.copy_from_slice(&value.to_bits().to_le_bytes());
+            }
+        },
+        _ => return Err("unknown filter")
+    }
+    return Ok(());
+}
+
+fn round_signed(v: f32) -> i32 {
+    return (v + if v >= 0.0 { 0.5 } else { -0.5 }) as i32;
+}
+
+fn read_signed(element: &[u8], component: usize, component_size: usize) -> i32 {
+    if component_size == 1 {
+        return element[component] as i8 as i32;
+    }
+    return i16::from_le_bytes([element[component * 2], element[component * 2 + 1]]) as i32;
+}
+
+fn write_signed(element: &mut [u8], component: usize, component_size: usize, value: i32) {
+    if component_size == 1 {
+        element[component] = value as i8 as u8;
+    } else {
+        element[component * 2..component * 2 + 2].copy_from_slice(&(value as i16).to_le_bytes());
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_decode_meshopt() {
+    // one 4 byte vertex: a zero delta group per byte channel, then the tail with the base vertex
+    let mut vertex_data = vec![VERTEX_HEADER, 0, 0, 0, 0];
+    vertex_data.extend_from_slice(&[0u8; VERTEX_TAIL_MAX_SIZE - 4]);
+    vertex_data.extend_from_slice(&[1, 2, 3, 4]);
+    assert_eq!(vec![1u8, 2, 3, 4], decode_vertex_buffer(1, 4, 4, &vertex_data).unwrap());
+
+    // single triangle of new vertices through codeaux table entry 0
+    let mut index_data = vec![TRIANGLE_HEADER | 1, 0xf0];
+    index_data.extend_from_slice(&[0x00, 0x76, 0x87, 0x56, 0x67, 0x78, 0xa9, 0x86, 0x65, 0x89, 0x68, 0x98, 0x01, 0x69, 0, 0]);
+    assert_eq!(vec![0u8, 0, 1, 0, 2, 0], decode_index_buffer(3, 2, 6, &index_data).unwrap());
+
+    // sequence 5, 6 on baseline 0: zigzag deltas +5 (10 << 1) and +1 (2 << 1)
+    let sequence_data = vec![SEQUENCE_HEADER | 1, 20, 4, 0, 0, 0, 0];
+    assert_eq!(vec![5u8, 0, 0, 0, 6, 0, 0, 0], decode_index_sequence(2, 4, 8, &sequence_data).unwrap());
+
+    // exponential: mantissa 3, exponent -1 = 1.5
+    let mut exp_data = ((0xffu32 << 24) | 3).to_le_bytes().to_vec();
+    apply_filter("EXPONENTIAL", &mut exp_data, 1, 4).unwrap();
+    assert_eq!(1.5f32.to_bits().to_le_bytes().to_vec(), exp_data);
+}
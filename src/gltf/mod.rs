@@ -1,7 +1,13 @@
 pub mod skeletal;
 pub mod mesh;
+pub mod material;
+pub mod scene;
+pub mod meshopt;
+pub mod writer;
 
 use std::collections::HashMap;
+use std::fmt;
+use std::io;
 use std::io::{Cursor, Read};
 
 use base64;
@@ -15,47 +21,311 @@ use serde::{Deserialize, Serialize};
 use crate::model::{Joint, Mesh, Rig, RigRemapTable, SkeletalMesh};
 use crate::render::gl_geometry::{VertexAttribs, MAX_JOINTS};
 use crate::anim::skeletal::{SkeletalAnimation, TRS};
-use crate::assets::Asset;
+use crate::assets::{Asset, load_asset};
 
-pub fn load_gltf(asset: &mut Asset) -> GltfFile {
-    let mut file: GltfFile = serde_json::from_slice(&asset.get_buffer().unwrap()).unwrap();
-    for buffer in &file.buffers {
-        const BASE64_PREFIX: &str = "data:application/octet-stream;base64,";
-        if !buffer.uri.starts_with(BASE64_PREFIX) {
-            panic!("load_gltf failed: buffer uri is not base 64 encoded");
+/// glb container magic "glTF"
+const GLB_MAGIC: u32 = 0x46546C67;
+/// glb chunk type "JSON"
+const GLB_CHUNK_JSON: u32 = 0x4E4F534A;
+/// glb chunk type "BIN\0"
+const GLB_CHUNK_BIN: u32 = 0x004E4942;
+const GLB_HEADER_LENGTH: usize = 12;
+const GLB_CHUNK_HEADER_LENGTH: usize = 8;
+
+/// extensions this loader understands, files requiring anything else are rejected
+const SUPPORTED_EXTENSIONS: [&str; 2] = ["KHR_mesh_quantization", "EXT_meshopt_compression"];
+
+pub fn load_gltf(asset: &mut Asset) -> Result<GltfFile, GltfError> {
+    let path = asset.path.clone();
+    let data = asset.get_buffer().map_err(|e| GltfError::AssetRead(path.clone(), e))?;
+    return load_gltf_from_slice(data, &path);
+}
+
+/// load a gltf file from either a json (.gltf) or a binary (.glb) container
+/// path is the asset path of the file, relative buffer and image uris are resolved against it
+pub fn load_gltf_from_slice(data: &[u8], path: &str) -> Result<GltfFile, GltfError> {
+    let mut bin_chunk: Option<&[u8]> = None;
+    let mut file: GltfFile = if is_glb(data) {
+        let glb = parse_glb(data)?;
+        bin_chunk = glb.bin;
+        serde_json::from_slice(glb.json).map_err(GltfError::Json)?
+    } else {
+        serde_json::from_slice(data).map_err(GltfError::Json)?
+    };
+    file.path = path.to_owned();
+    validate_nodes(&file)?;
+
+    if let Some(required) = &file.extensions_required {
+        for extension in required {
+            if !SUPPORTED_EXTENSIONS.contains(&extension.as_str()) {
+                return Err(GltfError::UnsupportedExtension(extension.clone()));
+            }
+        }
+    }
+
+    for (buffer_index, buffer) in file.buffers.iter().enumerate() {
+        if buffer.is_meshopt_fallback() {
+            // placeholder for uncompressed data, only referenced by compressed buffer views
+            file.decoded_buffers.push(Vec::new());
+            continue;
+        }
+        let data = match &buffer.uri {
+            Some(uri) => load_uri(&file.path, uri)?,
+            None => {
+                // glb: the first buffer without an uri refers to the binary chunk
+                if buffer_index != 0 || bin_chunk.is_none() {
+                    return Err(GltfError::Glb(format!("buffer {} has no uri and no binary chunk", buffer_index)));
+                }
+                bin_chunk.unwrap().to_vec()
+            }
+        };
+        if data.len() < buffer.byte_length {
+            return Err(GltfError::BufferTooShort { buffer: buffer_index, expected: buffer.byte_length, actual: data.len() });
         }
-        let data = base64::decode(&buffer.uri[BASE64_PREFIX.len()..]).unwrap();
         file.decoded_buffers.push(data);
     }
-    return file;
+
+    let mut decoded_buffer_views = HashMap::new();
+    for (buffer_view_index, buffer_view) in file.buffer_views.iter().enumerate() {
+        let compression = buffer_view.extensions.as_ref().and_then(|e| e.meshopt_compression.as_ref());
+        if compression.is_some() {
+            let decoded = decode_meshopt_buffer_view(&file, buffer_view_index, buffer_view, compression.unwrap())?;
+            decoded_buffer_views.insert(buffer_view_index, decoded);
+        }
+    }
+    file.decoded_buffer_views = decoded_buffer_views;
+    return Ok(file);
+}
+
+/// decode an EXT_meshopt_compression buffer view into its uncompressed bytes
+fn decode_meshopt_buffer_view(file: &GltfFile, buffer_view_index: usize, buffer_view: &GltfBufferView,
+                              compression: &GltfMeshoptBufferView) -> Result<Vec<u8>, GltfError> {
+    let buffer = file.decoded_buffers.get(compression.buffer)
+        .ok_or(GltfError::MissingBufferView(buffer_view_index))?;
+    let end = compression.byte_offset.checked_add(compression.byte_length).unwrap_or(usize::MAX);
+    if end > buffer.len() {
+        return Err(GltfError::BufferTooShort { buffer: compression.buffer, expected: end, actual: buffer.len() });
+    }
+    if compression.count.checked_mul(compression.byte_stride) != Some(buffer_view.byte_length) {
+        return Err(GltfError::Meshopt { buffer_view: buffer_view_index, reason: "count * byteStride does not match the buffer view byteLength" });
+    }
+    let filter = compression.filter.as_ref().map_or("NONE", |f| f.as_str());
+    let decoded = meshopt::decode_buffer_view(&compression.mode, filter, compression.count, compression.byte_stride,
+                                              buffer_view.byte_length, &buffer[compression.byte_offset..end])
+        .map_err(|reason| GltfError::Meshopt { buffer_view: buffer_view_index, reason })?;
+    debug!("decoded meshopt buffer view {}: {} -> {} bytes ({}, {})",
+           buffer_view_index, compression.byte_length, decoded.len(), compression.mode, filter);
+    return Ok(decoded);
+}
+
+/// check node references and transform arrays up front so later traversal can index freely
+fn validate_nodes(file: &GltfFile) -> Result<(), GltfError> {
+    let mesh_count = file.meshes.as_ref().map_or(0, |meshes| meshes.len());
+    let skin_count = file.skins.as_ref().map_or(0, |skins| skins.len());
+    for (node_index, node) in file.nodes.iter().enumerate() {
+        let invalid = |reason: String| GltfError::InvalidNode { node: node_index, reason };
+        let lengths = [(&node.translation, 3, "translation"), (&node.rotation, 4, "rotation"),
+            (&node.scale, 3, "scale"), (&node.matrix, 16, "matrix")];
+        for (values, expected, property) in lengths.iter() {
+            if let Some(values) = values {
+                if values.len() != *expected {
+                    return Err(invalid(format!("{} has {} components, expected {}", property, values.len(), expected)));
+                }
+            }
+        }
+        if let Some(children) = &node.children {
+            if let Some(child) = children.iter().find(|&&child| child >= file.nodes.len()) {
+                return Err(invalid(format!("child node {} does not exist", child)));
+            }
+        }
+        if let Some(mesh) = node.mesh.filter(|&mesh| mesh >= mesh_count) {
+            return Err(invalid(format!("mesh {} does not exist", mesh)));
+        }
+        if let Some(skin) = node.skin.filter(|&skin| skin >= skin_count) {
+            return Err(invalid(format!("skin {} does not exist", skin)));
+        }
+    }
+    return Ok(());
+}
+
+/// load the encoded (png, jpeg, ...) bytes of a gltf image, either from its uri or its buffer view
+pub fn load_image_data(file: &GltfFile, image_index: usize) -> Result<Vec<u8>, GltfError> {
+    let images = file.images.as_ref().ok_or(GltfError::MissingImage(image_index))?;
+    let image = images.get(image_index).ok_or(GltfError::MissingImage(image_index))?;
+    if let Some(uri) = &image.uri {
+        return load_uri(&file.path, uri);
+    }
+    if let Some(buffer_view_index) = image.buffer_view {
+        return Ok(buffer_view_data(file, buffer_view_index)?.to_vec());
+    }
+    return Err(GltfError::MissingImage(image_index));
+}
+
+/// resolve a buffer or image uri to its bytes.
+/// data uris are decoded in place, everything else is loaded through the asset system
+/// relative to the gltf file at base_path
+fn load_uri(base_path: &str, uri: &str) -> Result<Vec<u8>, GltfError> {
+    if uri.starts_with("data:") {
+        const BASE64_MARKER: &str = ";base64,";
+        let data_start = uri.find(BASE64_MARKER)
+            .ok_or_else(|| GltfError::InvalidUri(uri.to_owned(), "data uri is not base64 encoded"))?;
+        return base64::decode(&uri[data_start + BASE64_MARKER.len()..])
+            .map_err(|_| GltfError::InvalidUri(uri.to_owned(), "invalid base64 data"));
+    }
+    if uri.contains("://") || uri.starts_with('/') {
+        return Err(GltfError::InvalidUri(uri.to_owned(), "only relative uris are supported"));
+    }
+
+    let decoded = percent_decode(uri)?;
+    let path = resolve_relative_path(base_path, &decoded)?;
+    let mut asset = load_asset(&path).ok_or_else(|| GltfError::MissingAsset(path.clone()))?;
+    let data = asset.get_buffer().map_err(|e| GltfError::AssetRead(path.clone(), e))?;
+    return Ok(data.to_vec());
+}
+
+/// decode %XX escapes in a uri
+fn percent_decode(uri: &str) -> Result<String, GltfError> {
+    let bytes = uri.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = uri.get(i + 1..i + 3)
+                .ok_or_else(|| GltfError::InvalidUri(uri.to_owned(), "truncated percent escape"))?;
+            let byte = u8::from_str_radix(hex, 16)
+                .map_err(|_| GltfError::InvalidUri(uri.to_owned(), "invalid percent escape"))?;
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    if decoded.contains(&0) {
+        return Err(GltfError::InvalidUri(uri.to_owned(), "nul byte in path"));
+    }
+    return String::from_utf8(decoded).map_err(|_| GltfError::InvalidUri(uri.to_owned(), "path is not valid utf-8"));
+}
+
+/// join a relative path onto the directory of base_path, collapsing "." and ".." segments
+fn resolve_relative_path(base_path: &str, relative: &str) -> Result<String, GltfError> {
+    let mut segments: Vec<&str> = base_path.split('/').collect();
+    // drop the file name of the base path
+    segments.pop();
+    for segment in relative.split('/') {
+        if segment == "." || segment.is_empty() {
+            continue;
+        }
+        if segment == ".." {
+            if segments.pop().is_none() {
+                return Err(GltfError::InvalidUri(relative.to_owned(), "path escapes the asset root"));
+            }
+            continue;
+        }
+        segments.push(segment);
+    }
+    return Ok(segments.join("/"));
+}
+
+/// json and binary chunks of a glb container
+struct GlbChunks<'a> {
+    json: &'a [u8],
+    bin: Option<&'a [u8]>
+}
+
+fn is_glb(data: &[u8]) -> bool {
+    if data.len() < GLB_HEADER_LENGTH {
+        return false;
+    }
+    return Cursor::new(data).read_u32::<LittleEndian>().unwrap() == GLB_MAGIC;
+}
+
+/// split a glb container into its json and (optional) binary chunk
+/// layout: header (magic, version, length), then chunks of (length, type, data)
+fn parse_glb(data: &[u8]) -> Result<GlbChunks<'_>, GltfError> {
+    let mut cursor = Cursor::new(data);
+    let magic = cursor.read_u32::<LittleEndian>().unwrap();
+    let version = cursor.read_u32::<LittleEndian>().unwrap();
+    let length = cursor.read_u32::<LittleEndian>().unwrap() as usize;
+    if magic != GLB_MAGIC {
+        return Err(GltfError::Glb(format!("bad magic {:#x}", magic)));
+    }
+    if version != 2 {
+        return Err(GltfError::Glb(format!("unsupported version {}", version)));
+    }
+    if length > data.len() {
+        return Err(GltfError::Glb(format!("header length {} exceeds data length {}", length, data.len())));
+    }
+
+    let mut json = None;
+    let mut bin = None;
+    let mut offset = GLB_HEADER_LENGTH;
+    while offset + GLB_CHUNK_HEADER_LENGTH <= length {
+        cursor.set_position(offset as u64);
+        let chunk_length = cursor.read_u32::<LittleEndian>().unwrap() as usize;
+        let chunk_type = cursor.read_u32::<LittleEndian>().unwrap();
+        let chunk_start = offset + GLB_CHUNK_HEADER_LENGTH;
+        let chunk_end = chunk_start + chunk_length;
+        if chunk_end > length {
+            return Err(GltfError::Glb(format!("chunk {:#x} overruns container", chunk_type)));
+        }
+        let chunk = &data[chunk_start..chunk_end];
+        if chunk_type == GLB_CHUNK_JSON && json.is_none() {
+            json = Some(chunk);
+        } else if chunk_type == GLB_CHUNK_BIN && bin.is_none() {
+            bin = Some(chunk);
+        } else {
+            // unknown chunk types must be ignored
+            debug!("parse_glb: skipping chunk type {:#x} length {}", chunk_type, chunk_length);
+        }
+        // chunks are 4 byte aligned
+        offset = (chunk_end + 3) & !3;
+    }
+
+    let json = json.ok_or_else(|| GltfError::Glb("missing json chunk".to_owned()))?;
+    return Ok(GlbChunks { json, bin });
 }
 
+/// node local transform, from its matrix if present, otherwise from its translation, rotation and scale
 fn matrix_from_gltf_node(node: &GltfNode) -> float4x4 {
+    if let Some(values) = &node.matrix {
+        // 16 components are checked by validate_nodes
+        return matrix_from_column_major(values);
+    }
     let trs = trs_from_gltf_node(&node);
     return matrix4x4_trs(&trs.0, &trs.1, &trs.2);
 }
 
+/// gltf matrices are column-major, float4x4 is row-major
+fn matrix_from_column_major(values: &[f32]) -> float4x4 {
+    let mut matrix = matrix4x4_identity();
+    for row in 0..4 {
+        for column in 0..4 {
+            matrix.m[row][column] = values[column * 4 + row];
+        }
+    }
+    return matrix;
+}
+
 fn trs_from_gltf_node(node: &GltfNode) -> (float3, quaternion, float3) {
     let mut translation = float3::zero();
     let mut rotation = quaternion::identity();
     let mut scale = float3::new(1.0, 1.0, 1.0);
 
+    // component counts are checked by validate_nodes
     let node_translation = node.translation.as_ref();
     if node_translation.is_some() {
         let values = node_translation.unwrap();
-        assert_eq!(values.len(), 3);
         translation = float3::new(values[0], values[1], values[2]);
     }
     let node_rotation = node.rotation.as_ref();
     if node_rotation.is_some() {
         let values = node_rotation.unwrap();
-        assert_eq!(values.len(), 4);
         rotation = quaternion::new(values[0], values[1], values[2], values[3]);
     }
     let node_scale = node.scale.as_ref();
     if node_scale.is_some() {
         let values = node_scale.unwrap();
-        assert_eq!(values.len(), 3);
         scale = float3::new(values[0], values[1], values[2]);
     }
     return (translation, rotation, scale);
@@ -63,16 +333,202 @@ fn trs_from_gltf_node(node: &GltfNode) -> (float3, quaternion, float3) {
 
 
 
-/// compute a cursor into the gltf buffer for given buffer view
-fn get_buffer_cursor<'life>(gltf_file: &'life GltfFile, buffer_view: &GltfBufferView) -> Cursor<&'life Vec<u8>> {
-    let buffer = &gltf_file.decoded_buffers[buffer_view.buffer];
-    let mut cursor = Cursor::new(buffer);
-    cursor.set_position(buffer_view.byte_offset as u64);
-    return cursor;
+/// accessor by index
+fn get_accessor(file: &GltfFile, accessor_index: usize) -> Result<&GltfAccessor, GltfError> {
+    return file.accessors.get(accessor_index)
+        .ok_or_else(|| GltfError::InvalidAccessor { accessor: accessor_index, reason: "does not exist".to_owned() });
+}
+
+/// bounds checked bytes of a buffer view
+/// compressed buffer views resolve to their decoded bytes
+fn buffer_view_data(file: &GltfFile, buffer_view_index: usize) -> Result<&[u8], GltfError> {
+    if let Some(decoded) = file.decoded_buffer_views.get(&buffer_view_index) {
+        return Ok(decoded);
+    }
+    let buffer_view = file.buffer_views.get(buffer_view_index)
+        .ok_or(GltfError::MissingBufferView(buffer_view_index))?;
+    let buffer = file.decoded_buffers.get(buffer_view.buffer)
+        .ok_or(GltfError::MissingBufferView(buffer_view_index))?;
+    let end = buffer_view.byte_offset.checked_add(buffer_view.byte_length).unwrap_or(usize::MAX);
+    if end > buffer.len() {
+        return Err(GltfError::BufferTooShort { buffer: buffer_view.buffer, expected: end, actual: buffer.len() });
+    }
+    return Ok(&buffer[buffer_view.byte_offset..end]);
+}
+
+/// size in bytes of a single accessor component
+fn component_size(component_type: i64) -> Option<usize> {
+    return match component_type {
+        t if t == GltfComponentType::Byte as i64 || t == GltfComponentType::UnsignedByte as i64 => Some(1),
+        t if t == GltfComponentType::Short as i64 || t == GltfComponentType::UnsignedShort as i64 => Some(2),
+        t if t == GltfComponentType::UnsignedInt as i64 || t == GltfComponentType::Float as i64 => Some(4),
+        _ => None
+    };
+}
+
+/// number of components per accessor element
+fn component_count(accessor_type: &str) -> Option<usize> {
+    return match accessor_type {
+        "SCALAR" => Some(1),
+        "VEC2" => Some(2),
+        "VEC3" => Some(3),
+        "VEC4" => Some(4),
+        "MAT2" => Some(4),
+        "MAT3" => Some(9),
+        "MAT4" => Some(16),
+        _ => None
+    };
+}
+
+/// read every element of an accessor.
+/// honors the accessor byte offset and the buffer view byte stride (interleaved vertex data),
+/// read_element is handed a cursor over exactly one element, so reads within the element size can not fail
+fn read_accessor<T, F>(file: &GltfFile, accessor_index: usize, mut read_element: F) -> Result<Vec<T>, GltfError>
+    where F: FnMut(&mut Cursor<&[u8]>) -> T {
+    let accessor = get_accessor(file, accessor_index)?;
+    let component_size = component_size(accessor.component_type)
+        .ok_or_else(|| GltfError::InvalidAccessor { accessor: accessor_index,
+            reason: format!("unknown component type {}", accessor.component_type) })?;
+    let component_count = component_count(&accessor.accessor_type)
+        .ok_or_else(|| GltfError::InvalidAccessor { accessor: accessor_index,
+            reason: format!("unknown accessor type {}", accessor.accessor_type) })?;
+    let element_size = component_size * component_count;
+    let count = accessor_count(accessor_index, accessor)?;
+
+    let mut elements = Vec::new();
+    if accessor.sparse.is_none() && accessor.buffer_view.is_some() {
+        // bounds checked, count elements fit in the buffer view
+        let (data, stride) = strided_view_data(file, accessor_index, accessor.buffer_view.unwrap(), element_size)?;
+        elements.reserve(count);
+        for i in 0..count {
+            let start = accessor.byte_offset + i * stride;
+            let mut cursor = Cursor::new(&data[start..start + element_size]);
+            elements.push(read_element(&mut cursor));
+        }
+    } else {
+        let data = dense_accessor_data(file, accessor_index, element_size)?;
+        for element in data.chunks_exact(element_size) {
+            let mut cursor = Cursor::new(element);
+            elements.push(read_element(&mut cursor));
+        }
+    }
+    return Ok(elements);
+}
+
+/// accessor element count, negative counts are invalid
+fn accessor_count(accessor_index: usize, accessor: &GltfAccessor) -> Result<usize, GltfError> {
+    if accessor.count < 0 {
+        return Err(GltfError::InvalidAccessor { accessor: accessor_index, reason: format!("negative count {}", accessor.count) });
+    }
+    return Ok(accessor.count as usize);
+}
+
+/// bytes and element stride of the buffer view backing an accessor, bounds checked for every element
+fn strided_view_data(file: &GltfFile, accessor_index: usize, buffer_view_index: usize, element_size: usize)
+    -> Result<(&[u8], usize), GltfError> {
+    let accessor = &file.accessors[accessor_index];
+    let count = accessor_count(accessor_index, accessor)?;
+    let buffer_view = file.buffer_views.get(buffer_view_index)
+        .ok_or(GltfError::MissingBufferView(buffer_view_index))?;
+    let data = buffer_view_data(file, buffer_view_index)?;
+    let stride = buffer_view.byte_stride.unwrap_or(element_size);
+    if stride < element_size {
+        return Err(GltfError::InvalidAccessor { accessor: accessor_index,
+            reason: format!("byte stride {} < element size {}", stride, element_size) });
+    }
+    if count > 0 {
+        let required = stride.checked_mul(count - 1)
+            .and_then(|size| size.checked_add(element_size))
+            .and_then(|size| size.checked_add(accessor.byte_offset))
+            .ok_or_else(|| GltfError::InvalidAccessor { accessor: accessor_index, reason: "size overflows".to_owned() })?;
+        if required > data.len() {
+            return Err(GltfError::AccessorOutOfBounds { accessor: accessor_index, required, available: data.len() });
+        }
+    }
+    return Ok((data, stride));
+}
+
+/// tightly packed accessor elements: the base buffer view (or zeros without one)
+/// with the sparse values substituted at the sparse indices
+fn dense_accessor_data(file: &GltfFile, accessor_index: usize, element_size: usize) -> Result<Vec<u8>, GltfError> {
+    let accessor = &file.accessors[accessor_index];
+    let count = accessor_count(accessor_index, accessor)?;
+    let too_large = || GltfError::InvalidAccessor { accessor: accessor_index, reason: "size overflows".to_owned() };
+    let mut dense = vec![0u8; count.checked_mul(element_size).ok_or_else(too_large)?];
+    if let Some(buffer_view_index) = accessor.buffer_view {
+        let (data, stride) = strided_view_data(file, accessor_index, buffer_view_index, element_size)?;
+        for i in 0..count {
+            let start = accessor.byte_offset + i * stride;
+            dense[i * element_size..(i + 1) * element_size].copy_from_slice(&data[start..start + element_size]);
+        }
+    }
+
+    if let Some(sparse) = &accessor.sparse {
+        let index_size = match sparse.indices.component_type {
+            t if t == GltfComponentType::UnsignedByte as i64 => 1,
+            t if t == GltfComponentType::UnsignedShort as i64 => 2,
+            t if t == GltfComponentType::UnsignedInt as i64 => 4,
+            t => return Err(GltfError::InvalidAccessor { accessor: accessor_index,
+                reason: format!("unsupported sparse index component type {}", t) })
+        };
+        let indices = buffer_view_data(file, sparse.indices.buffer_view)?;
+        let values = buffer_view_data(file, sparse.values.buffer_view)?;
+        let indices_end = sparse.count.checked_mul(index_size)
+            .and_then(|size| size.checked_add(sparse.indices.byte_offset))
+            .ok_or_else(too_large)?;
+        if indices_end > indices.len() {
+            return Err(GltfError::AccessorOutOfBounds { accessor: accessor_index, required: indices_end, available: indices.len() });
+        }
+        let values_end = sparse.count.checked_mul(element_size)
+            .and_then(|size| size.checked_add(sparse.values.byte_offset))
+            .ok_or_else(too_large)?;
+        if values_end > values.len() {
+            return Err(GltfError::AccessorOutOfBounds { accessor: accessor_index, required: values_end, available: values.len() });
+        }
+
+        let mut index_cursor = Cursor::new(&indices[sparse.indices.byte_offset..indices_end]);
+        for i in 0..sparse.count {
+            let target = match index_size {
+                1 => index_cursor.read_u8().unwrap() as usize,
+                2 => index_cursor.read_u16::<LittleEndian>().unwrap() as usize,
+                _ => index_cursor.read_u32::<LittleEndian>().unwrap() as usize
+            };
+            if target >= count {
+                return Err(GltfError::InvalidAccessor { accessor: accessor_index,
+                    reason: format!("sparse index {} out of range for {} elements", target, count) });
+            }
+            let value_start = sparse.values.byte_offset + i * element_size;
+            dense[target * element_size..(target + 1) * element_size]
+                .copy_from_slice(&values[value_start..value_start + element_size]);
+        }
+    }
+    return Ok(dense);
+}
+
+/// read a single component as a float.
+/// normalized integer components map to [0, 1] (unsigned) or [-1, 1] (signed),
+/// other integer components (KHR_mesh_quantization) keep their integer value
+fn read_component(cursor: &mut Cursor<&[u8]>, component_type: i64, normalized: bool) -> f32 {
+    let (value, max) = match component_type {
+        t if t == GltfComponentType::Float as i64 => return cursor.read_f32::<LittleEndian>().unwrap(),
+        t if t == GltfComponentType::Byte as i64 => (cursor.read_i8().unwrap() as f32, 127.0),
+        t if t == GltfComponentType::UnsignedByte as i64 => (cursor.read_u8().unwrap() as f32, 255.0),
+        t if t == GltfComponentType::Short as i64 => (cursor.read_i16::<LittleEndian>().unwrap() as f32, 32767.0),
+        t if t == GltfComponentType::UnsignedShort as i64 => (cursor.read_u16::<LittleEndian>().unwrap() as f32, 65535.0),
+        // component_size rejects anything else before elements are read
+        _ => (cursor.read_u32::<LittleEndian>().unwrap() as f32, u32::MAX as f32)
+    };
+    if normalized {
+        // signed minimum (-128, -32768) clamps to -1
+        return (value / max).max(-1.0);
+    }
+    return value;
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GltfFile {
+    /// default scene index
+    pub scene: Option<usize>,
     pub meshes: Option<Vec<GltfMesh>>,
     #[serde(rename = "bufferViews")]
     pub buffer_views: Vec<GltfBufferView>,
@@ -82,10 +538,30 @@ pub struct GltfFile {
     pub nodes: Vec<GltfNode>,
     pub skins: Option<Vec<GltfSkin>>,
     pub animations: Option<Vec<GltfAnimation>>,
+    pub images: Option<Vec<GltfImage>>,
+    pub materials: Option<Vec<GltfMaterial>>,
+    pub textures: Option<Vec<GltfTexture>>,
+    pub samplers: Option<Vec<GltfSampler>>,
+    #[serde(rename = "extensionsUsed")]
+    pub extensions_used: Option<Vec<String>>,
+    #[serde(rename = "extensionsRequired")]
+    pub extensions_required: Option<Vec<String>>,
 
+    /// asset path this file was loaded from (not part of gltf)
+    #[serde(skip)]
+    pub path: String,
     /// decoded uri buffers (not part of gltf)
     #[serde(skip)]
-    pub decoded_buffers: Vec<Vec<u8>>
+    pub decoded_buffers: Vec<Vec<u8>>,
+    /// decoded EXT_meshopt_compression buffer views by index (not part of gltf)
+    #[serde(skip)]
+    pub decoded_buffer_views: HashMap<usize, Vec<u8>>
+}
+
+impl GltfFile {
+    pub fn uses_extension(&self, name: &str) -> bool {
+        return self.extensions_used.as_ref().map_or(false, |used| used.iter().any(|e| e == name));
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -97,12 +573,14 @@ pub struct GltfScene {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GltfNode {
+    #[serde(default)]
     pub name: String,
     pub children: Option<Vec<usize>>,
     pub translation: Option<Vec<f32>>,
     pub rotation: Option<Vec<f32>>,
     pub scale: Option<Vec<f32>>,
     pub matrix: Option<Vec<f32>>,
+    pub mesh: Option<usize>,
     pub skin: Option<usize>
 }
 
@@ -110,15 +588,18 @@ pub struct GltfNode {
 pub struct GltfSkin {
     pub name: String,
     #[serde(rename="inverseBindMatrices")]
-    pub inverse_bind_matrices: usize,
+    pub inverse_bind_matrices: Option<usize>,
     pub joints: Vec<usize>,
     pub skeleton: Option<usize>
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GltfMesh {
+    #[serde(default)]
     pub name: String,
-    pub primitives: Vec<GltfMeshPrimitive>
+    pub primitives: Vec<GltfMeshPrimitive>,
+    /// default morph target weights
+    pub weights: Option<Vec<f32>>
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -126,7 +607,9 @@ pub struct GltfMeshPrimitive {
     pub attributes: HashMap<String, usize>,
     pub indices: Option<usize>,
     pub material: Option<usize>,
-    pub mode: Option<i64>
+    pub mode: Option<i64>,
+    /// morph targets, attribute name -> accessor of deltas
+    pub targets: Option<Vec<HashMap<String, usize>>>
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -134,32 +617,194 @@ pub struct GltfBufferView {
     pub buffer: usize,
     #[serde(rename = "byteLength")]
     pub byte_length: usize,
-    #[serde(rename = "byteOffset")]
-    pub byte_offset: usize
+    #[serde(rename = "byteOffset", default)]
+    pub byte_offset: usize,
+    /// distance in bytes between vertex attribute elements, tightly packed if absent
+    #[serde(rename = "byteStride")]
+    pub byte_stride: Option<usize>,
+    pub target: Option<i64>,
+    pub extensions: Option<GltfBufferViewExtensions>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GltfBufferViewExtensions {
+    #[serde(rename = "EXT_meshopt_compression")]
+    pub meshopt_compression: Option<GltfMeshoptBufferView>
+}
+
+/// compressed source of a buffer view, byteOffset/byteLength of the view itself refer to the fallback buffer
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GltfMeshoptBufferView {
+    pub buffer: usize,
+    #[serde(rename = "byteOffset", default)]
+    pub byte_offset: usize,
+    #[serde(rename = "byteLength")]
+    pub byte_length: usize,
+    #[serde(rename = "byteStride")]
+    pub byte_stride: usize,
+    pub count: usize,
+    /// ATTRIBUTES, TRIANGLES or INDICES
+    pub mode: String,
+    /// NONE, OCTAHEDRAL, QUATERNION or EXPONENTIAL
+    pub filter: Option<String>
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GltfBuffer {
     #[serde(rename = "byteLength")]
     pub byte_length: usize,
-    pub uri: String
+    /// absent for the glb binary chunk buffer
+    pub uri: Option<String>,
+    pub extensions: Option<GltfBufferExtensions>
+}
+
+impl GltfBuffer {
+    /// EXT_meshopt_compression fallback buffers may have no data at all
+    fn is_meshopt_fallback(&self) -> bool {
+        let compression = self.extensions.as_ref().and_then(|e| e.meshopt_compression.as_ref());
+        return self.uri.is_none() && compression.map_or(false, |c| c.fallback);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GltfBufferExtensions {
+    #[serde(rename = "EXT_meshopt_compression")]
+    pub meshopt_compression: Option<GltfMeshoptBuffer>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GltfMeshoptBuffer {
+    #[serde(default)]
+    pub fallback: bool
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GltfImage {
+    pub name: Option<String>,
+    pub uri: Option<String>,
+    #[serde(rename = "mimeType")]
+    pub mime_type: Option<String>,
+    #[serde(rename = "bufferView")]
+    pub buffer_view: Option<usize>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GltfMaterial {
+    pub name: Option<String>,
+    #[serde(rename = "pbrMetallicRoughness")]
+    pub pbr_metallic_roughness: Option<GltfPbrMetallicRoughness>,
+    #[serde(rename = "normalTexture")]
+    pub normal_texture: Option<GltfTextureInfo>,
+    #[serde(rename = "occlusionTexture")]
+    pub occlusion_texture: Option<GltfTextureInfo>,
+    #[serde(rename = "emissiveTexture")]
+    pub emissive_texture: Option<GltfTextureInfo>,
+    #[serde(rename = "emissiveFactor")]
+    pub emissive_factor: Option<Vec<f32>>,
+    /// OPAQUE, MASK or BLEND
+    #[serde(rename = "alphaMode")]
+    pub alpha_mode: Option<String>,
+    #[serde(rename = "alphaCutoff")]
+    pub alpha_cutoff: Option<f32>,
+    #[serde(rename = "doubleSided", default)]
+    pub double_sided: bool
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GltfPbrMetallicRoughness {
+    #[serde(rename = "baseColorFactor")]
+    pub base_color_factor: Option<Vec<f32>>,
+    #[serde(rename = "baseColorTexture")]
+    pub base_color_texture: Option<GltfTextureInfo>,
+    #[serde(rename = "metallicFactor")]
+    pub metallic_factor: Option<f32>,
+    #[serde(rename = "roughnessFactor")]
+    pub roughness_factor: Option<f32>,
+    #[serde(rename = "metallicRoughnessTexture")]
+    pub metallic_roughness_texture: Option<GltfTextureInfo>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GltfTextureInfo {
+    pub index: usize,
+    #[serde(rename = "texCoord", default)]
+    pub tex_coord: usize,
+    /// normal textures only
+    pub scale: Option<f32>,
+    /// occlusion textures only
+    pub strength: Option<f32>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GltfTexture {
+    pub sampler: Option<usize>,
+    /// image index, absent when the image is only provided through an extension
+    pub source: Option<usize>
+}
+
+/// filter and wrap values are gl enums
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GltfSampler {
+    #[serde(rename = "magFilter")]
+    pub mag_filter: Option<u32>,
+    #[serde(rename = "minFilter")]
+    pub min_filter: Option<u32>,
+    #[serde(rename = "wrapS")]
+    pub wrap_s: Option<u32>,
+    #[serde(rename = "wrapT")]
+    pub wrap_t: Option<u32>
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GltfAccessor {
+    /// absent for accessors that are all zeros apart from their sparse values
     #[serde(rename = "bufferView")]
-    pub buffer_view: usize,
+    pub buffer_view: Option<usize>,
+    /// offset in bytes relative to the start of the buffer view
+    #[serde(rename = "byteOffset", default)]
+    pub byte_offset: usize,
     #[serde(rename = "componentType")]
     pub component_type: i64,
+    /// integer components map to [0, 1] (unsigned) or [-1, 1] (signed)
+    #[serde(default)]
+    pub normalized: bool,
     #[serde(rename = "type")]
     pub accessor_type: String,
     pub count: i64,
     pub min: Option<Vec<f32>>,
-    pub max: Option<Vec<f32>>
+    pub max: Option<Vec<f32>>,
+    pub sparse: Option<GltfAccessorSparse>
+}
+
+/// elements that differ from the base buffer view (or from zero)
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GltfAccessorSparse {
+    pub count: usize,
+    pub indices: GltfAccessorSparseIndices,
+    pub values: GltfAccessorSparseValues
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GltfAccessorSparseIndices {
+    #[serde(rename = "bufferView")]
+    pub buffer_view: usize,
+    #[serde(rename = "byteOffset", default)]
+    pub byte_offset: usize,
+    #[serde(rename = "componentType")]
+    pub component_type: i64
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GltfAccessorSparseValues {
+    #[serde(rename = "bufferView")]
+    pub buffer_view: usize,
+    #[serde(rename = "byteOffset", default)]
+    pub byte_offset: usize
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GltfAnimation {
+    #[serde(default)]
     pub name: String,
     pub channels: Vec<GltfAnimationChannel>,
     pub samplers: Vec<GltfAnimationSampler>
@@ -174,6 +819,8 @@ pub struct GltfAnimationChannel {
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GltfAnimationSampler {
     pub input: usize,
+    /// LINEAR (default when empty), STEP or CUBICSPLINE
+    #[serde(default)]
     pub interpolation: String,
     pub output: usize
 }
@@ -191,4 +838,195 @@ pub enum GltfComponentType {
     UnsignedShort = 5123,
     UnsignedInt = 5125,
     Float = 5126
-}
\ No newline at end of file
+}
+
+#[derive(Debug)]
+pub enum GltfError {
+    /// malformed gltf json
+    Json(serde_json::Error),
+    /// malformed glb container
+    Glb(String),
+    /// uri that can not be resolved, with the reason
+    InvalidUri(String, &'static str),
+    /// asset path that does not exist
+    MissingAsset(String),
+    /// asset path that exists but can not be read
+    AssetRead(String, io::Error),
+    /// output path that can not be written
+    Write(String, io::Error),
+    /// buffer data shorter than its declared length
+    BufferTooShort { buffer: usize, expected: usize, actual: usize },
+    /// image index that does not exist or has no source
+    MissingImage(usize),
+    /// buffer view index that does not exist or refers to a missing buffer
+    MissingBufferView(usize),
+    /// accessor that can not be read, with the reason
+    InvalidAccessor { accessor: usize, reason: String },
+    /// accessor elements reaching past the end of their buffer view
+    AccessorOutOfBounds { accessor: usize, required: usize, available: usize },
+    /// extension listed in extensionsRequired that this loader does not implement
+    UnsupportedExtension(String),
+    /// compressed buffer view that fails to decode, with the reason
+    Meshopt { buffer_view: usize, reason: &'static str },
+    /// node with invalid references or transform, with the reason
+    InvalidNode { node: usize, reason: String },
+    /// mesh (by name) that can not be used as requested, with the reason
+    InvalidMesh { mesh: String, reason: String },
+    /// mesh primitive that can not be loaded, with the reason
+    InvalidPrimitive { mesh: usize, primitive: usize, reason: String },
+    /// skin with invalid joints, with the reason
+    InvalidSkin { skin: usize, reason: String },
+    /// material with invalid texture references, with the reason
+    InvalidMaterial { material: usize, reason: String },
+    /// animation channel that can not be loaded, with the reason
+    InvalidChannel { animation: usize, channel: usize, reason: String },
+    /// file has no meshes
+    NoMeshes,
+    /// file has no skinned mesh node
+    NoSkin,
+    /// file has no animations
+    NoAnimations,
+    /// file has no scene with root nodes
+    NoScene
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GltfError::Json(e) => write!(f, "invalid gltf json: {}", e),
+            GltfError::Glb(reason) => write!(f, "invalid glb container: {}", reason),
+            GltfError::InvalidUri(uri, reason) => write!(f, "invalid uri {:?}: {}", uri, reason),
+            GltfError::MissingAsset(path) => write!(f, "missing asset {:?}", path),
+            GltfError::AssetRead(path, e) => write!(f, "failed to read asset {:?}: {}", path, e),
+            GltfError::Write(path, e) => write!(f, "failed to write {:?}: {}", path, e),
+            GltfError::BufferTooShort { buffer, expected, actual } =>
+                write!(f, "buffer {} too short: expected {} bytes, got {}", buffer, expected, actual),
+            GltfError::MissingImage(image) => write!(f, "missing image {}", image),
+            GltfError::MissingBufferView(buffer_view) => write!(f, "missing buffer view {}", buffer_view),
+            GltfError::InvalidAccessor { accessor, reason } => write!(f, "invalid accessor {}: {}", accessor, reason),
+            GltfError::AccessorOutOfBounds { accessor, required, available } =>
+                write!(f, "accessor {} out of bounds: requires {} bytes of buffer view, has {}", accessor, required, available),
+            GltfError::UnsupportedExtension(extension) => write!(f, "unsupported required extension {}", extension),
+            GltfError::Meshopt { buffer_view, reason } =>
+                write!(f, "failed to decode compressed buffer view {}: {}", buffer_view, reason),
+            GltfError::InvalidNode { node, reason } => write!(f, "invalid node {}: {}", node, reason),
+            GltfError::InvalidMesh { mesh, reason } => write!(f, "invalid mesh {}: {}", mesh, reason),
+            GltfError::InvalidPrimitive { mesh, primitive, reason } =>
+                write!(f, "invalid primitive {} of mesh {}: {}", primitive, mesh, reason),
+            GltfError::InvalidSkin { skin, reason } => write!(f, "invalid skin {}: {}", skin, reason),
+            GltfError::InvalidMaterial { material, reason } => write!(f, "invalid material {}: {}", material, reason),
+            GltfError::InvalidChannel { animation, channel, reason } =>
+                write!(f, "invalid channel {} of animation {}: {}", channel, animation, reason),
+            GltfError::NoMeshes => write!(f, "file has no meshes"),
+            GltfError::NoSkin => write!(f, "file has no skinned mesh"),
+            GltfError::NoAnimations => write!(f, "file has no animations"),
+            GltfError::NoScene => write!(f, "file has no scene")
+        }
+    }
+}
+
+impl std::error::Error for GltfError {}
+
+#[cfg(test)]
+#[test]
+fn test_load_glb() {
+    use byteorder::WriteBytesExt;
+
+    let mut json = br#"{"buffers":[{"byteLength":4}],"bufferViews":[],"accessors":[],"scenes":[],"nodes":[]}"#.to_vec();
+    while json.len() % 4 != 0 {
+        json.push(b' ');
+    }
+    let bin: [u8; 4] = [1, 2, 3, 4];
+    let mut glb: Vec<u8> = Vec::new();
+    glb.write_u32::<LittleEndian>(GLB_MAGIC).unwrap();
+    glb.write_u32::<LittleEndian>(2).unwrap();
+    glb.write_u32::<LittleEndian>((GLB_HEADER_LENGTH + 2 * GLB_CHUNK_HEADER_LENGTH + json.len() + bin.len()) as u32).unwrap();
+    glb.write_u32::<LittleEndian>(json.len() as u32).unwrap();
+    glb.write_u32::<LittleEndian>(GLB_CHUNK_JSON).unwrap();
+    glb.extend_from_slice(&json);
+    glb.write_u32::<LittleEndian>(bin.len() as u32).unwrap();
+    glb.write_u32::<LittleEndian>(GLB_CHUNK_BIN).unwrap();
+    glb.extend_from_slice(&bin);
+
+    let file = load_gltf_from_slice(&glb, "test.glb").unwrap();
+    assert_eq!(1, file.decoded_buffers.len());
+    assert_eq!(vec![1u8, 2, 3, 4], file.decoded_buffers[0]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_resolve_uri_path() {
+    assert_eq!("resources/mesh bear.bin", resolve_relative_path("resources/bear.gltf", &percent_decode("mesh%20bear.bin").unwrap()).unwrap());
+    assert_eq!("shared/anim.bin", resolve_relative_path("resources/bear.gltf", "../shared/./anim.bin").unwrap());
+    assert_eq!("mesh.bin", resolve_relative_path("tabletop.gltf", "mesh.bin").unwrap());
+    assert!(resolve_relative_path("tabletop.gltf", "../mesh.bin").is_err());
+    assert!(percent_decode("bad%2").is_err());
+    assert!(percent_decode("bad%zz").is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_read_accessor_interleaved() {
+    use byteorder::WriteBytesExt;
+
+    // two vertices of interleaved position (vec3) + uv (vec2), stride 20
+    let mut data: Vec<u8> = Vec::new();
+    for v in [0.0f32, 1.0, 2.0, 10.0, 11.0, 3.0, 4.0, 5.0, 12.0, 13.0].iter() {
+        data.write_f32::<LittleEndian>(*v).unwrap();
+    }
+    let json = format!(r#"{{
+        "buffers": [{{ "byteLength": 40, "uri": "data:application/octet-stream;base64,{}" }}],
+        "bufferViews": [{{ "buffer": 0, "byteLength": 40, "byteStride": 20 }}],
+        "accessors": [
+            {{ "bufferView": 0, "componentType": 5126, "count": 2, "type": "VEC3" }},
+            {{ "bufferView": 0, "byteOffset": 12, "componentType": 5126, "count": 2, "type": "VEC2" }},
+            {{ "bufferView": 0, "byteOffset": 12, "componentType": 5126, "count": 3, "type": "VEC2" }}
+        ],
+        "scenes": [], "nodes": []
+    }}"#, base64::encode(&data));
+    let file = load_gltf_from_slice(json.as_bytes(), "test.gltf").unwrap();
+
+    let read_vec2 = |cursor: &mut Cursor<&[u8]>| {
+        (cursor.read_f32::<LittleEndian>().unwrap(), cursor.read_f32::<LittleEndian>().unwrap())
+    };
+    let positions = read_accessor(&file, 0, |cursor| cursor.read_f32::<LittleEndian>().unwrap()).unwrap();
+    assert_eq!(vec![0.0, 3.0], positions);
+    assert_eq!(vec![(10.0, 11.0), (12.0, 13.0)], read_accessor(&file, 1, read_vec2).unwrap());
+    assert!(read_accessor(&file, 2, read_vec2).is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_read_accessor_sparse() {
+    use byteorder::WriteBytesExt;
+
+    // base [1, 2, 3, 4], sparse indices [1, 3] (u16, padded to 4 bytes), sparse values [20, 40]
+    let mut data: Vec<u8> = Vec::new();
+    for v in [1.0f32, 2.0, 3.0, 4.0].iter() {
+        data.write_f32::<LittleEndian>(*v).unwrap();
+    }
+    data.write_u16::<LittleEndian>(1).unwrap();
+    data.write_u16::<LittleEndian>(3).unwrap();
+    data.write_f32::<LittleEndian>(20.0).unwrap();
+    data.write_f32::<LittleEndian>(40.0).unwrap();
+    let json = format!(r#"{{
+        "buffers": [{{ "byteLength": 28, "uri": "data:application/octet-stream;base64,{}" }}],
+        "bufferViews": [
+            {{ "buffer": 0, "byteLength": 16 }},
+            {{ "buffer": 0, "byteOffset": 16, "byteLength": 4 }},
+            {{ "buffer": 0, "byteOffset": 20, "byteLength": 8 }}
+        ],
+        "accessors": [
+            {{ "bufferView": 0, "componentType": 5126, "count": 4, "type": "SCALAR",
+               "sparse": {{ "count": 2, "indices": {{ "bufferView": 1, "componentType": 5123 }}, "values": {{ "bufferView": 2 }} }} }},
+            {{ "componentType": 5126, "count": 4, "type": "SCALAR",
+               "sparse": {{ "count": 2, "indices": {{ "bufferView": 1, "componentType": 5123 }}, "values": {{ "bufferView": 2 }} }} }}
+        ],
+        "scenes": [], "nodes": []
+    }}"#, base64::encode(&data));
+    let file = load_gltf_from_slice(json.as_bytes(), "test.gltf").unwrap();
+
+    let read_f32 = |cursor: &mut Cursor<&[u8]>| cursor.read_f32::<LittleEndian>().unwrap();
+    assert_eq!(vec![1.0, 20.0, 3.0, 40.0], read_accessor(&file, 0, read_f32).unwrap());
+    assert_eq!(vec![0.0, 20.0, 0.0, 40.0], read_accessor(&file, 1, read_f32).unwrap());
+}
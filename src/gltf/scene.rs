@@ -0,0 +1,51 @@
+use math::matrix::{float4x4, matrix4x4_identity, matrix4x4_mul};
+
+use crate::gltf::{matrix_from_gltf_node, GltfError, GltfFile};
+use crate::model::NodeInstance;
+
+/// walk a scene depth-first and return every node with its world matrix.
+/// scene_index None imports the file's default scene (or the first scene)
+pub fn import_scene(scene_index: Option<usize>, file: &GltfFile) -> Result<Vec<NodeInstance>, GltfError> {
+    let scene_index = scene_index.or(file.scene).unwrap_or(0);
+    let scene = file.scenes.get(scene_index).ok_or(GltfError::NoScene)?;
+    info!("import scene {} {:?}", scene_index, scene.name);
+
+    let mut instances: Vec<NodeInstance> = Vec::new();
+    let mut visited = vec![false; file.nodes.len()];
+    if let Some(root_nodes) = &scene.root_nodes {
+        for root_node in root_nodes.iter() {
+            if *root_node >= file.nodes.len() {
+                return Err(GltfError::InvalidNode { node: *root_node, reason: "scene root does not exist".to_owned() });
+            }
+            recur_import_node(&mut instances, &mut visited, *root_node, &matrix4x4_identity(), file)?;
+        }
+    }
+    debug!("import_scene: {} nodes", instances.len());
+    return Ok(instances);
+}
+
+fn recur_import_node(instances: &mut Vec<NodeInstance>, visited: &mut Vec<bool>, node_index: usize, parent: &float4x4,
+                     file: &GltfFile) -> Result<(), GltfError> {
+    if visited[node_index] {
+        return Err(GltfError::InvalidNode { node: node_index, reason: "node has more than one parent".to_owned() });
+    }
+    visited[node_index] = true;
+
+    let node = &file.nodes[node_index];
+    let world = matrix4x4_mul(parent, &matrix_from_gltf_node(node));
+    instances.push(NodeInstance {
+        node: node_index,
+        name: node.name.clone(),
+        mesh: node.mesh,
+        skin: node.skin,
+        world_matrix: world
+    });
+
+    // child indices are checked by validate_nodes
+    if let Some(children) = &node.children {
+        for child in children.iter() {
+            recur_import_node(instances, visited, *child, &world, file)?;
+        }
+    }
+    return Ok(());
+}
@@ -1,123 +1,214 @@
 use std::collections::HashMap;
+use std::rc::Rc;
 
 use byteorder::{LittleEndian, ReadBytesExt};
 use math::matrix::{float4x4, matrix4x4_identity, matrix4x4_inverse, matrix4x4_mul, matrix4x4_trs};
 use math::quaternion::quaternion;
 use math::vector::float3;
 
-use crate::anim::skeletal::{SkeletalAnimation, TRS};
-use crate::gltf::{get_buffer_cursor, GltfAnimation, GltfComponentType, GltfFile, GltfNode, trs_from_gltf_node};
-use crate::gltf::mesh::load_mesh;
+use crate::anim::retarget::{retarget_animation, RetargetMap};
+use crate::anim::skeletal::{AnimationLibrary, InterpMethod, MorphWeightTrack, SkeletalAnimation, TRS};
+use crate::gltf::{get_accessor, matrix_from_column_major, matrix_from_gltf_node, read_accessor, read_component, GltfAnimation, GltfComponentType, GltfError, GltfFile, GltfNode, trs_from_gltf_node};
+use crate::gltf::mesh::load_mesh_group_at;
 use crate::model::{Joint, Rig, RigRemapTable, SkeletalMesh};
 use math::inverse_lerp;
 
-pub fn load_animations(skeletal_mesh: &SkeletalMesh, file: &GltfFile) -> SkeletalAnimation {
-    if file.animations.is_none() {
-        panic!("no animations");
-    }
-    let animations = &file.animations.as_ref().unwrap();
-    if animations.len() > 1 {
-        panic!("multiple animations not yet supported");
-    }
+/// load every animation in the file into a library keyed by animation name.
+/// all clips are remapped onto the rig of skeletal_mesh, which requires both files to share node order
+pub fn load_animations(skeletal_mesh: &SkeletalMesh, file: &GltfFile) -> Result<AnimationLibrary, GltfError> {
+    return load_rig_animations(&skeletal_mesh.rig, file);
+}
 
-    let anim: &GltfAnimation = &animations[0];
-    let anim_name = (&anim).name.clone();
-    info!("load animation: {}, channels {}, samplers {}", anim.name, anim.channels.len(), anim.samplers.len());
+/// load every animation in the file onto a rig built from the same file
+pub fn load_rig_animations(rig: &Rig, file: &GltfFile) -> Result<AnimationLibrary, GltfError> {
+    let animations = file.animations.as_ref().filter(|animations| !animations.is_empty())
+        .ok_or(GltfError::NoAnimations)?;
+    let mut library = AnimationLibrary::new();
+    for anim_index in 0..animations.len() {
+        library.insert(load_animation(rig, file, anim_index)?);
+    }
+    info!("loaded {} animations: {:?}", library.len(), library.names());
+    return Ok(library);
+}
 
-    // load rig scale
-    let scene = &file.scenes[0];
-    let scene_root_nodes = scene.root_nodes.as_ref().unwrap();
-    let rig_node: &GltfNode = &file.nodes[scene_root_nodes[0]];
-    let mut rig_scale = 1.0;
-    if rig_node.scale.is_some() {
-        let components = rig_node.scale.as_ref().unwrap();
-        if components[0] != components[1] || components[0] != components[2] {
-            panic!("non uniform rig scale {},{},{}", components[0], components[1], components[2]);
-        }
-        rig_scale = components[0];
+/// load every animation in the file on its own rig and retarget the clips onto skeletal_mesh by joint name.
+/// aliases map joint names of the animation file to joint names of the mesh
+pub fn load_retargeted_animations(skeletal_mesh: &SkeletalMesh, file: &GltfFile, aliases: &HashMap<String, String>)
+    -> Result<AnimationLibrary, GltfError> {
+    let source_rig = load_animation_rig(file)?;
+    let source_library = load_rig_animations(&source_rig, file)?;
+    let map = RetargetMap::by_name(&source_rig, &skeletal_mesh.rig, aliases);
+    let mut library = AnimationLibrary::new();
+    for name in source_library.names() {
+        library.insert(retarget_animation(&source_library.get(name).unwrap(), &map, &source_rig, &skeletal_mesh.rig));
     }
-    info!("rig scale {}", rig_scale);
+    return Ok(library);
+}
+
+fn load_animation(rig: &Rig, file: &GltfFile, anim_index: usize)
+    -> Result<SkeletalAnimation, GltfError> {
+    let anim: &GltfAnimation = &file.animations.as_ref().unwrap()[anim_index];
+    let anim_name = if anim.name.is_empty() { format!("animation_{}", anim_index) } else { anim.name.clone() };
+    info!("load animation: {}, channels {}, samplers {}", anim_name, anim.channels.len(), anim.samplers.len());
 
     // read samples
     let mut sparse_channels: HashMap<usize, SparseChannel> = HashMap::new();
-    for gltf_channel in anim.channels.iter() {
-        // use remapped target bone index
-        let bone_index = skeletal_mesh.rig.remap_table.joints[&gltf_channel.target.node];
+    let mut morph_weights: Vec<SparseWeights> = Vec::new();
+    for (channel_index, gltf_channel) in anim.channels.iter().enumerate() {
+        let invalid_channel = |reason: String| GltfError::InvalidChannel { animation: anim_index, channel: channel_index, reason };
+
+        let sampler = anim.samplers.get(gltf_channel.sampler)
+            .ok_or_else(|| invalid_channel(format!("sampler {} does not exist", gltf_channel.sampler)))?;
+        let input_accessor = get_accessor(file, sampler.input)?;
+        let output_accessor = get_accessor(file, sampler.output)?;
+
+        let interp = match sampler.interpolation.as_str() {
+            "" | "LINEAR" => InterpMethod::Linear,
+            "STEP" => InterpMethod::Step,
+            "CUBICSPLINE" => InterpMethod::CubicSpline,
+            other => return Err(invalid_channel(format!("unknown sampler interpolation {}", other)))
+        };
+        // weights channels animate every morph target of the node's mesh, one output per target
+        let is_weights = gltf_channel.target.path == "weights";
+        let target_count = if is_weights {
+            morph_target_count(gltf_channel.target.node, file)
+                .ok_or_else(|| invalid_channel(format!("target node {} has no morph targets", gltf_channel.target.node)))?
+        } else {
+            1
+        };
+        // cubic spline outputs are (in-tangent, value, out-tangent) triples
+        let outputs_per_input = (if interp == InterpMethod::CubicSpline { 3 } else { 1 }) * target_count as i64;
+        if input_accessor.count.checked_mul(outputs_per_input) != Some(output_accessor.count) {
+            return Err(invalid_channel(format!("sampler input count {} does not match output count {} for {:?}",
+                                               input_accessor.count, output_accessor.count, interp)));
+        }
+        if input_accessor.component_type != GltfComponentType::Float as i64 {
+            return Err(invalid_channel(format!("sampler input component type != float: {:?}", input_accessor.component_type)));
+        }
+        if input_accessor.min.is_none() || input_accessor.max.is_none() {
+            return Err(invalid_channel("sampler input missing min|max bounds".to_owned()));
+        }
+        if input_accessor.accessor_type != "SCALAR" {
+            return Err(invalid_channel(format!("sampler input incorrect accessor type {:?} expected SCALAR", input_accessor.accessor_type)));
+        }
+        let output_type = match gltf_channel.target.path.as_str() {
+            "translation" | "scale" => "VEC3",
+            "rotation" => "VEC4",
+            _ => ""
+        };
+        if !output_type.is_empty() && (output_accessor.accessor_type != output_type
+            || output_accessor.component_type != GltfComponentType::Float as i64) {
+            return Err(invalid_channel(format!("sampler output {} {:?} is not a float {}",
+                                               gltf_channel.target.path, output_accessor.accessor_type, output_type)));
+        }
+        if is_weights && output_accessor.accessor_type != "SCALAR" {
+            return Err(invalid_channel(format!("sampler output weights {:?} is not SCALAR", output_accessor.accessor_type)));
+        }
+        let frame_times = read_accessor(file, sampler.input, |cursor| {
+            cursor.read_f32::<LittleEndian>().unwrap()
+        })?;
+        let time_min = input_accessor.min.as_ref().unwrap()[0];
+        let time_max = input_accessor.max.as_ref().unwrap()[0];
+
+        if is_weights {
+            if morph_weights.iter().any(|weights| weights.node == gltf_channel.target.node) {
+                warn!("animation {}: node {} has more than one weights channel, using the first", anim_name, gltf_channel.target.node);
+                continue;
+            }
+            let (component_type, normalized) = (output_accessor.component_type, output_accessor.normalized);
+            let outputs = read_accessor(file, sampler.output, |cursor| read_component(cursor, component_type, normalized))?;
+            let outputs: Vec<Vec<f32>> = outputs.chunks(target_count).map(|weights| weights.to_vec()).collect();
+            let (values, tangents) = split_cubic_outputs(outputs, interp);
+            morph_weights.push(SparseWeights {
+                node: gltf_channel.target.node,
+                values,
+                tangents,
+                times: frame_times,
+                time_min,
+                time_max,
+                interp
+            });
+            continue;
+        }
+
+        // use remapped target bone index, channels of other skins in the file are skipped
+        let bone_index = match rig.remap_table.joints.get(&gltf_channel.target.node) {
+            Some(bone_index) => *bone_index,
+            None if is_skin_joint(gltf_channel.target.node, file) => {
+                debug!("animation {}: skipping channel of node {} from another skin", anim_name, gltf_channel.target.node);
+                continue;
+            },
+            None => return Err(invalid_channel(format!("target node {} is not a joint of the rig", gltf_channel.target.node)))
+        };
 
         if !sparse_channels.contains_key(&bone_index) {
             sparse_channels.insert(bone_index, SparseChannel {
                 translations: vec![],
+                translation_tangents: vec![],
                 translation_times: vec![],
                 translation_time_min: 0.0,
                 translation_time_max: 0.0,
+                translation_interp: InterpMethod::Linear,
                 rotations: vec![],
+                rotation_tangents: vec![],
                 rotation_times: vec![],
                 rotation_time_min: 0.0,
                 rotation_time_max: 0.0,
+                rotation_interp: InterpMethod::Linear,
                 scales: vec![],
+                scale_tangents: vec![],
                 scale_times: vec![],
                 scale_time_min: 0.0,
-                scale_time_max: 0.0
+                scale_time_max: 0.0,
+                scale_interp: InterpMethod::Linear
             });
         }
-        let mut channel = sparse_channels.get_mut(&bone_index).unwrap();
 
-        let sampler = &anim.samplers[gltf_channel.sampler];
-        let input_accessor = &file.accessors[sampler.input];
-        let output_accessor = &file.accessors[sampler.output];
+        let mut channel = sparse_channels.get_mut(&bone_index).unwrap();
 
-        if input_accessor.count != output_accessor.count {
-            // todo doesn't hold true for some samplers
-            panic!("sampler input/output count mismatch");
-        }
-        if input_accessor.component_type != GltfComponentType::Float as i64 {
-            panic!("sampler input component type != float: {:?}", input_accessor.component_type);
-        }
-        if input_accessor.min.is_none() || input_accessor.max.is_none() {
-            panic!("sampler input missing min|max bounds");
-        }
-        if input_accessor.accessor_type != "SCALAR" {
-            panic!("sampler input incorrect accessor type {:?} expected SCALAR", input_accessor.accessor_type);
-        }
-        // TODO treat everything as linear for now
-        /*if sampler.interpolation != "LINEAR" {
-            panic!("unsupported sampler interpolation {}", sampler.interpolation);
-        }*/
-
-        let input_buffer_view = &file.buffer_views[input_accessor.buffer_view];
-        let output_buffer_view = &file.buffer_views[output_accessor.buffer_view];
-        let mut input_cursor = get_buffer_cursor(&file, input_buffer_view);
-        let mut output_cursor = get_buffer_cursor(&file, output_buffer_view);
-
-        for f in 0..input_accessor.count as usize {
-            let frame_time = input_cursor.read_f32::<LittleEndian>().unwrap();
-            if gltf_channel.target.path == "translation" {
-                let x = output_cursor.read_f32::<LittleEndian>().unwrap();
-                let y = output_cursor.read_f32::<LittleEndian>().unwrap();
-                let z = output_cursor.read_f32::<LittleEndian>().unwrap();
-                // note: scale translations by rig scale
-                channel.translations.push(float3::new(x, y, z) * rig_scale);
-                channel.translation_times.push(frame_time);
-                channel.translation_time_min = input_accessor.min.as_ref().unwrap()[0];
-                channel.translation_time_max = input_accessor.max.as_ref().unwrap()[0];
-            } else if gltf_channel.target.path == "rotation" {
-                let x = output_cursor.read_f32::<LittleEndian>().unwrap();
-                let y = output_cursor.read_f32::<LittleEndian>().unwrap();
-                let z = output_cursor.read_f32::<LittleEndian>().unwrap();
-                let w = output_cursor.read_f32::<LittleEndian>().unwrap();
-                channel.rotations.push(quaternion::new(x, y, z, w));
-                channel.rotation_times.push(frame_time);
-                channel.rotation_time_min = input_accessor.min.as_ref().unwrap()[0];
-                channel.rotation_time_max = input_accessor.max.as_ref().unwrap()[0];
-            } else if gltf_channel.target.path == "scale" {
-                let x = output_cursor.read_f32::<LittleEndian>().unwrap();
-                let y = output_cursor.read_f32::<LittleEndian>().unwrap();
-                let z = output_cursor.read_f32::<LittleEndian>().unwrap();
-                channel.scales.push(float3::new(x, y, z));
-                channel.scale_times.push(frame_time);
-                channel.scale_time_min = input_accessor.min.as_ref().unwrap()[0];
-                channel.scale_time_max = input_accessor.max.as_ref().unwrap()[0];
-            }
+        if gltf_channel.target.path == "translation" {
+            let translations = read_accessor(file, sampler.output, |cursor| {
+                let x = cursor.read_f32::<LittleEndian>().unwrap();
+                let y = cursor.read_f32::<LittleEndian>().unwrap();
+                let z = cursor.read_f32::<LittleEndian>().unwrap();
+                float3::new(x, y, z)
+            })?;
+            let (values, tangents) = split_cubic_outputs(translations, interp);
+            channel.translations.extend(values);
+            channel.translation_tangents.extend(tangents);
+            channel.translation_times.extend(frame_times.iter());
+            channel.translation_time_min = time_min;
+            channel.translation_time_max = time_max;
+            channel.translation_interp = interp;
+        } else if gltf_channel.target.path == "rotation" {
+            let rotations = read_accessor(file, sampler.output, |cursor| {
+                let x = cursor.read_f32::<LittleEndian>().unwrap();
+                let y = cursor.read_f32::<LittleEndian>().unwrap();
+                let z = cursor.read_f32::<LittleEndian>().unwrap();
+                let w = cursor.read_f32::<LittleEndian>().unwrap();
+                quaternion::new(x, y, z, w)
+            })?;
+            let (values, tangents) = split_cubic_outputs(rotations, interp);
+            channel.rotations.extend(values);
+            channel.rotation_tangents.extend(tangents);
+            channel.rotation_times.extend(frame_times.iter());
+            channel.rotation_time_min = time_min;
+            channel.rotation_time_max = time_max;
+            channel.rotation_interp = interp;
+        } else if gltf_channel.target.path == "scale" {
+            let scales = read_accessor(file, sampler.output, |cursor| {
+                let x = cursor.read_f32::<LittleEndian>().unwrap();
+                let y = cursor.read_f32::<LittleEndian>().unwrap();
+                let z = cursor.read_f32::<LittleEndian>().unwrap();
+                float3::new(x, y, z)
+            })?;
+            let (values, tangents) = split_cubic_outputs(scales, interp);
+            channel.scales.extend(values);
+            channel.scale_tangents.extend(tangents);
+            channel.scale_times.extend(frame_times.iter());
+            channel.scale_time_min = time_min;
+            channel.scale_time_max = time_max;
+            channel.scale_interp = interp;
         }
     }
 
@@ -151,6 +242,10 @@ pub fn load_animations(skeletal_mesh: &SkeletalMesh, file: &GltfFile) -> Skeleta
             max_time = channel.scale_time_max;
         }
     }
+    for weights in morph_weights.iter() {
+        min_time = min_time.min(weights.time_min);
+        max_time = max_time.max(weights.time_max);
+    }
     debug!("animation time bounds: min={}, max={}", min_time, max_time);
 
     // make dense
@@ -163,31 +258,77 @@ pub fn load_animations(skeletal_mesh: &SkeletalMesh, file: &GltfFile) -> Skeleta
         num_frames: frame_count,
         min_time,
         max_time,
-        joints: vec![vec![TRS::default(); frame_count]; skeletal_mesh.rig.joint_count]
+        joints: vec![vec![TRS::default(); frame_count]; rig.joint_count],
+        morph_weights: Vec::new(),
+        additive: false
     };
-    //make_dense_presampled(&mut dense, &skeletal_mesh.rig, &sparse_channels);
-    make_dense(&mut dense, &skeletal_mesh.rig, time_step, &sparse_channels);
+    //make_dense_presampled(&mut dense, rig, &sparse_channels);
+    make_dense(&mut dense, rig, time_step, &sparse_channels);
+    dense.morph_weights = morph_weights.iter().map(|weights| MorphWeightTrack {
+        node: file.nodes[weights.node].name.clone(),
+        frames: (0..frame_count)
+            .map(|frame| sample_weights(time_step * (frame as f32), &weights.times, &weights.values, &weights.tangents, weights.interp))
+            .collect()
+    }).collect();
 
     /*debug!("finished loading animation: frames={}, frame_min={}, frame_max={}",
            animation.frame_count, animation.frame_min, animation.frame_max);*/
-    return dense;
+    return Ok(dense);
 }
 
+/// keyframes per path, tangents are (in, out) per keyframe and only present for CubicSpline
 struct SparseChannel {
     pub translations: Vec<float3>,
+    pub translation_tangents: Vec<(float3, float3)>,
     pub translation_times: Vec<f32>,
     pub translation_time_min: f32,
     pub translation_time_max: f32,
+    pub translation_interp: InterpMethod,
 
     pub rotations: Vec<quaternion>,
+    pub rotation_tangents: Vec<(quaternion, quaternion)>,
     pub rotation_times: Vec<f32>,
     pub rotation_time_min: f32,
     pub rotation_time_max: f32,
+    pub rotation_interp: InterpMethod,
 
     pub scales: Vec<float3>,
+    pub scale_tangents: Vec<(float3, float3)>,
     pub scale_times: Vec<f32>,
     pub scale_time_min: f32,
-    pub scale_time_max: f32
+    pub scale_time_max: f32,
+    pub scale_interp: InterpMethod
+}
+
+/// morph target weight keyframes of a mesh node animated by a clip, one weight per target per keyframe
+struct SparseWeights {
+    pub node: usize,
+    pub values: Vec<Vec<f32>>,
+    pub tangents: Vec<(Vec<f32>, Vec<f32>)>,
+    pub times: Vec<f32>,
+    pub time_min: f32,
+    pub time_max: f32,
+    pub interp: InterpMethod
+}
+
+/// number of morph targets of the mesh instanced by a node, the largest target count of its primitives
+fn morph_target_count(node_index: usize, file: &GltfFile) -> Option<usize> {
+    let mesh_index = file.nodes.get(node_index)?.mesh?;
+    let mesh = file.meshes.as_ref()?.get(mesh_index)?;
+    let target_count = mesh.primitives.iter()
+        .map(|primitive| primitive.targets.as_ref().map_or(0, |targets| targets.len()))
+        .max()?;
+    return if target_count > 0 { Some(target_count) } else { None };
+}
+
+/// split cubic spline (in-tangent, value, out-tangent) triples into values and (in, out) tangents
+fn split_cubic_outputs<T: Clone>(outputs: Vec<T>, interp: InterpMethod) -> (Vec<T>, Vec<(T, T)>) {
+    if interp != InterpMethod::CubicSpline {
+        return (outputs, Vec::new());
+    }
+    let values = outputs.chunks(3).map(|triple| triple[1].clone()).collect();
+    let tangents = outputs.chunks(3).map(|triple| (triple[0].clone(), triple[2].clone())).collect();
+    return (values, tangents);
 }
 
 fn make_dense(dense: &mut SkeletalAnimation, rig: &Rig, time_step: f32, sparse_channels: &HashMap<usize, SparseChannel>) {
@@ -203,23 +344,106 @@ fn make_dense(dense: &mut SkeletalAnimation, rig: &Rig, time_step: f32, sparse_c
         for frame in 0..dense.num_frames {
             let time = time_step * (frame as f32);
 
-            let pair_translation: ((f32, &float3), (f32, &float3)) = select_keyframes::<float3>(time, &sparse.translation_times, &sparse.translations, &identity_translation);
-            let pair_rotation = select_keyframes::<quaternion>(time, &sparse.rotation_times, &sparse.rotations, &identity_rotation);
-            let pair_scale = select_keyframes::<float3>(time, &sparse.scale_times, &sparse.scales, &identity_scale);
-
-            let alpha_translation = inverse_lerp((pair_translation.0).0, (pair_translation.1).0, time);
-            let alpha_rotation = inverse_lerp((pair_rotation.0).0, (pair_rotation.1).0, time);
-            let alpha_scale = inverse_lerp((pair_scale.0).0, (pair_scale.1).0, time);
-
-            let translation = float3::lerp((pair_translation.0).1, (pair_translation.1).1, alpha_translation);
-            let rotation = quaternion::slerp((pair_rotation.0).1, (pair_rotation.1).1, alpha_rotation);
-            let scale = float3::lerp((pair_scale.0).1, (pair_scale.1).1, alpha_scale);
+            let translation = sample_float3(time, &sparse.translation_times, &sparse.translations,
+                                            &sparse.translation_tangents, sparse.translation_interp, &identity_translation);
+            let rotation = sample_quaternion(time, &sparse.rotation_times, &sparse.rotations,
+                                             &sparse.rotation_tangents, sparse.rotation_interp, &identity_rotation);
+            let scale = sample_float3(time, &sparse.scale_times, &sparse.scales,
+                                      &sparse.scale_tangents, sparse.scale_interp, &identity_scale);
 
             joint_frames[frame] = TRS { translation, rotation, scale };
         }
     }
 }
 
+/// evaluate a translation or scale channel at time
+fn sample_float3(time: f32, keyframe_times: &Vec<f32>, keyframes: &Vec<float3>, tangents: &Vec<(float3, float3)>,
+                 interp: InterpMethod, identity: &float3) -> float3 {
+    let (l, r) = search_keyframe_indices(time, keyframe_times);
+    if l.is_some() && r.is_some() {
+        let (l, r) = (l.unwrap(), r.unwrap());
+        let dt = keyframe_times[r] - keyframe_times[l];
+        let alpha = inverse_lerp(keyframe_times[l], keyframe_times[r], time);
+        return match interp {
+            InterpMethod::Step => keyframes[l],
+            InterpMethod::Linear => float3::lerp(&keyframes[l], &keyframes[r], alpha),
+            InterpMethod::CubicSpline => {
+                let (h00, h10, h01, h11) = hermite_basis(alpha);
+                keyframes[l] * h00 + tangents[l].1 * (dt * h10) + keyframes[r] * h01 + tangents[r].0 * (dt * h11)
+            }
+        };
+    }
+    // before the first or after the last keyframe
+    let ((left_time, left), (right_time, right)) = select_keyframes::<float3>(time, keyframe_times, keyframes, identity);
+    return float3::lerp(left, right, inverse_lerp(left_time, right_time, time));
+}
+
+/// evaluate a rotation channel at time, cubic results are renormalized
+fn sample_quaternion(time: f32, keyframe_times: &Vec<f32>, keyframes: &Vec<quaternion>, tangents: &Vec<(quaternion, quaternion)>,
+                     interp: InterpMethod, identity: &quaternion) -> quaternion {
+    let (l, r) = search_keyframe_indices(time, keyframe_times);
+    if l.is_some() && r.is_some() {
+        let (l, r) = (l.unwrap(), r.unwrap());
+        let dt = keyframe_times[r] - keyframe_times[l];
+        let alpha = inverse_lerp(keyframe_times[l], keyframe_times[r], time);
+        return match interp {
+            InterpMethod::Step => keyframes[l],
+            InterpMethod::Linear => quaternion::slerp(&keyframes[l], &keyframes[r], alpha),
+            InterpMethod::CubicSpline => {
+                let (h00, h10, h01, h11) = hermite_basis(alpha);
+                let (p0, m0, p1, m1) = (&keyframes[l], &tangents[l].1, &keyframes[r], &tangents[r].0);
+                let x = p0.x * h00 + m0.x * dt * h10 + p1.x * h01 + m1.x * dt * h11;
+                let y = p0.y * h00 + m0.y * dt * h10 + p1.y * h01 + m1.y * dt * h11;
+                let z = p0.z * h00 + m0.z * dt * h10 + p1.z * h01 + m1.z * dt * h11;
+                let w = p0.w * h00 + m0.w * dt * h10 + p1.w * h01 + m1.w * dt * h11;
+                let length = (x * x + y * y + z * z + w * w).sqrt();
+                if length > 0.0 {
+                    quaternion::new(x / length, y / length, z / length, w / length)
+                } else {
+                    *p0
+                }
+            }
+        };
+    }
+    // before the first or after the last keyframe
+    let ((left_time, left), (right_time, right)) = select_keyframes::<quaternion>(time, keyframe_times, keyframes, identity);
+    return quaternion::slerp(left, right, inverse_lerp(left_time, right_time, time));
+}
+
+/// evaluate a morph target weights channel at time, the nearest keyframe is held outside the keyframe range
+fn sample_weights(time: f32, keyframe_times: &Vec<f32>, keyframes: &Vec<Vec<f32>>, tangents: &Vec<(Vec<f32>, Vec<f32>)>,
+                  interp: InterpMethod) -> Vec<f32> {
+    let (l, r) = search_keyframe_indices(time, keyframe_times);
+    if l.is_some() && r.is_some() {
+        let (l, r) = (l.unwrap(), r.unwrap());
+        let dt = keyframe_times[r] - keyframe_times[l];
+        let alpha = inverse_lerp(keyframe_times[l], keyframe_times[r], time);
+        return match interp {
+            InterpMethod::Step => keyframes[l].clone(),
+            InterpMethod::Linear => keyframes[l].iter().zip(keyframes[r].iter())
+                .map(|(a, b)| a + (b - a) * alpha)
+                .collect(),
+            InterpMethod::CubicSpline => {
+                let (h00, h10, h01, h11) = hermite_basis(alpha);
+                (0..keyframes[l].len())
+                    .map(|i| keyframes[l][i] * h00 + tangents[l].1[i] * dt * h10 + keyframes[r][i] * h01 + tangents[r].0[i] * dt * h11)
+                    .collect()
+            }
+        };
+    }
+    return match l.or(r) {
+        Some(i) => keyframes[i].clone(),
+        None => Vec::new()
+    };
+}
+
+/// cubic hermite basis functions (h00, h10, h01, h11) at t
+fn hermite_basis(t: f32) -> (f32, f32, f32, f32) {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    return (2.0 * t3 - 3.0 * t2 + 1.0, t3 - 2.0 * t2 + t, -2.0 * t3 + 3.0 * t2, t3 - t2);
+}
+
 fn make_dense_presampled(dense: &mut SkeletalAnimation, rig: &Rig, sparse_channels: &HashMap<usize, SparseChannel>) {
     for joint_idx in 0..rig.joint_count {
         if !sparse_channels.contains_key(&joint_idx) {
@@ -275,7 +499,7 @@ fn search_keyframe_indices(time: f32, keyframe_times: &Vec<f32>) -> (Option<usiz
     // sweep from left to right and break on first occurrence of right
     for i in 0..keyframe_times.len() {
         let t = keyframe_times[i];
-        if t < time {
+        if t <= time {
             left = Some(i);
         }
         if t > time && right == None {
@@ -290,23 +514,84 @@ fn search_keyframe_indices(time: f32, keyframe_times: &Vec<f32>) -> (Option<usiz
 fn test_search_keyframes() {
     let test1_kf = vec![0.0, 1.0, 2.0, 3.0];
     let (test1_left, test1_right) = search_keyframe_indices(1.5, &test1_kf);
-    assert_eq!(1, test1_left);
-    assert_eq!(2, test1_right);
+    assert_eq!(Some(1), test1_left);
+    assert_eq!(Some(2), test1_right);
 }
 
-pub fn load_rig(file: &GltfFile) -> Rig {
+#[cfg(test)]
+#[test]
+fn test_sample_interpolation() {
+    let times = vec![0.0, 1.0];
+    let keyframes = vec![float3::zero(), float3::new(2.0, 0.0, 0.0)];
+    let flat_tangents = vec![(float3::zero(), float3::zero()); 2];
+    let identity = float3::zero();
+    let sample = |time: f32, interp: InterpMethod| sample_float3(time, &times, &keyframes, &flat_tangents, interp, &identity).x;
+    assert_eq!(0.0, sample(0.5, InterpMethod::Step));
+    assert_eq!(1.0, sample(0.5, InterpMethod::Linear));
+    // zero tangents: symmetric ease, exact keyframe values at the ends
+    assert_eq!(1.0, sample(0.5, InterpMethod::CubicSpline));
+    assert!(sample(0.25, InterpMethod::CubicSpline) < sample(0.25, InterpMethod::Linear));
+    assert_eq!(2.0, sample(1.0, InterpMethod::CubicSpline));
+}
+
+/// load the rig of the first skinned node's skin
+pub fn load_rig(file: &GltfFile) -> Result<Rig, GltfError> {
+    return load_skin_rig(find_skin(file)?, file);
+}
+
+/// skin of the first skinned node
+fn find_skin(file: &GltfFile) -> Result<usize, GltfError> {
     let skinned_mesh_node = file.nodes.iter()
         .find(|n| n.skin.is_some())
-        .expect("no skinned mesh found");
+        .ok_or(GltfError::NoSkin)?;
     info!("skinned mesh {}", skinned_mesh_node.name);
-    let file_skins = &file.skins.as_ref().unwrap();
-    let skin_index = skinned_mesh_node.skin.unwrap();
-    let skin = &file_skins[skin_index];
-    let root_bone_node_index = skin.joints[0];
-    let root_bone = &file.nodes[root_bone_node_index];
-    info!("root bone: {}", root_bone.name);
+    // skin indices are checked by validate_nodes
+    return Ok(skinned_mesh_node.skin.unwrap());
+}
 
-    let skin_indices_array = skin.joints.clone();
+/// build a rig from the joints of a skin.
+/// joints whose parent node is not a joint are roots, a skin may have several
+pub fn load_skin_rig(skin_index: usize, file: &GltfFile) -> Result<Rig, GltfError> {
+    let skin = &file.skins.as_ref().unwrap()[skin_index];
+    let invalid_skin = |reason: String| GltfError::InvalidSkin { skin: skin_index, reason };
+    if let Some(joint) = skin.joints.iter().find(|&&joint| joint >= file.nodes.len()) {
+        return Err(invalid_skin(format!("joint node {} does not exist", joint)));
+    }
+    if skin.joints.is_empty() {
+        return Err(invalid_skin("skin has no joints".to_owned()));
+    }
+    return build_rig(&skin.joints, file).map_err(invalid_skin);
+}
+
+/// rig the animations of a file are authored against: the first skin,
+/// or the animated nodes for animation-only files without a skin
+pub fn load_animation_rig(file: &GltfFile) -> Result<Rig, GltfError> {
+    if file.skins.as_ref().map_or(false, |skins| !skins.is_empty()) {
+        return load_skin_rig(0, file);
+    }
+    let mut animated_nodes: Vec<usize> = file.animations.iter().flatten()
+        .flat_map(|anim| anim.channels.iter())
+        .filter(|channel| channel.target.path != "weights")
+        .map(|channel| channel.target.node)
+        .collect();
+    animated_nodes.sort();
+    animated_nodes.dedup();
+    if let Some(&node) = animated_nodes.iter().find(|&&node| node >= file.nodes.len()) {
+        return Err(GltfError::InvalidNode { node, reason: "animated node does not exist".to_owned() });
+    }
+    if animated_nodes.is_empty() {
+        return Err(GltfError::NoAnimations);
+    }
+    return build_rig(&animated_nodes, file)
+        .map_err(|reason| GltfError::InvalidNode { node: animated_nodes[0], reason });
+}
+
+fn build_rig(joint_nodes: &Vec<usize>, file: &GltfFile) -> Result<Rig, String> {
+    let node_parents = node_parents(file);
+    let root_nodes: Vec<usize> = joint_nodes.iter().cloned()
+        .filter(|&joint| node_parents[joint].map_or(true, |parent| !joint_nodes.contains(&parent)))
+        .collect();
+    info!("root joints: {:?}", root_nodes.iter().map(|&node| &file.nodes[node].name).collect::<Vec<_>>());
 
     // load rig
     let mut rig = Rig {
@@ -315,30 +600,43 @@ pub fn load_rig(file: &GltfFile) -> Rig {
         joint_names: Vec::new(),
         joint_children: Vec::new(),
         joint_parents: Vec::new(),
+        root_joints: Vec::new(),
+        root_parent_transforms: Vec::new(),
         remap_table: RigRemapTable { joints: HashMap::new() },
     };
-    recur_build_rig(&mut rig, 0, root_bone_node_index, &file, root_bone);
+    for &root_node in root_nodes.iter() {
+        rig.root_joints.push(rig.joint_transforms.len());
+        // nodes above the skeleton (e.g. a scaled armature) still transform the joints
+        let parent_transform = node_parents[root_node]
+            .map_or(matrix4x4_identity(), |parent| node_world_matrix(parent, &node_parents, file));
+        rig.root_parent_transforms.push(parent_transform);
+        recur_build_rig(&mut rig, None, root_node, joint_nodes, file);
+    }
     rig.joint_count = rig.joint_transforms.len();
+    if rig.joint_count != joint_nodes.len() {
+        return Err(format!("{} joints reachable from the roots, expected {}", rig.joint_count, joint_nodes.len()));
+    }
 
     debug!("load_rig: finished with {} total bones", rig.joint_transforms.len());
-    return rig;
+    return Ok(rig);
 }
 
-/// depth-first recursive
+/// depth-first recursive, only descends into joint nodes
 /// source_bone_index = joint index in source animation file (for remap table)
-fn recur_build_rig(rig: &mut Rig, parent_joint_index: usize, source_joint_index: usize, file: &GltfFile, node: &GltfNode) {
+fn recur_build_rig(rig: &mut Rig, parent_joint_index: Option<usize>, source_joint_index: usize, joint_nodes: &Vec<usize>, file: &GltfFile) {
     let joint_index = rig.joint_transforms.len();
+    let node = &file.nodes[source_joint_index];
 
-    // remap children as we walk the hierarchy (root joint is always index zero)
+    // remap children as we walk the hierarchy (the first root joint is always index zero)
     rig.remap_table.joints.insert(source_joint_index, joint_index);
     debug!("remap source joint {} idx {} to {}", node.name, source_joint_index, joint_index);
 
-    let joint = joint_from_gltf_node(node, joint_index, parent_joint_index);
+    let joint = joint_from_gltf_node(node, joint_index, parent_joint_index.unwrap_or(0));
     rig.joint_transforms.push(joint);
     rig.joint_names.push(node.name.clone());
-    rig.joint_parents.push(parent_joint_index);
+    rig.joint_parents.push(parent_joint_index.unwrap_or(0));
     rig.joint_children.push(Vec::<usize>::new());
-    if joint_index != 0 {
+    if let Some(parent_joint_index) = parent_joint_index {
         // add this joint to its parents child indices array
         rig.joint_children[parent_joint_index].push(joint_index);
     }
@@ -346,10 +644,42 @@ fn recur_build_rig(rig: &mut Rig, parent_joint_index: usize, source_joint_index:
         // first add children
         let child_node_indices = node.children.as_ref().unwrap();
         for child_node_index in child_node_indices.iter() {
-            let child_node = &file.nodes[*child_node_index];
-            recur_build_rig(rig, joint_index, *child_node_index, file, child_node);
+            if joint_nodes.contains(child_node_index) && !rig.remap_table.joints.contains_key(child_node_index) {
+                recur_build_rig(rig, Some(joint_index), *child_node_index, joint_nodes, file);
+            }
+        }
+    }
+}
+
+fn is_skin_joint(node_index: usize, file: &GltfFile) -> bool {
+    return file.skins.iter().flatten().any(|skin| skin.joints.contains(&node_index));
+}
+
+/// parent node index per node
+fn node_parents(file: &GltfFile) -> Vec<Option<usize>> {
+    let mut parents = vec![None; file.nodes.len()];
+    for (node_index, node) in file.nodes.iter().enumerate() {
+        for &child in node.children.iter().flatten() {
+            parents[child] = Some(node_index);
         }
     }
+    return parents;
+}
+
+/// world transform of a node, composed from its ancestors
+fn node_world_matrix(node_index: usize, node_parents: &Vec<Option<usize>>, file: &GltfFile) -> float4x4 {
+    let mut world = matrix_from_gltf_node(&file.nodes[node_index]);
+    let mut ancestor = node_parents[node_index];
+    // bounded by the node count in case of a malformed cycle
+    for _ in 0..file.nodes.len() {
+        let parent = match ancestor {
+            Some(parent) => parent,
+            None => break
+        };
+        world = matrix4x4_mul(&matrix_from_gltf_node(&file.nodes[parent]), &world);
+        ancestor = node_parents[parent];
+    }
+    return world;
 }
 
 fn joint_from_gltf_node(node: &GltfNode, joint_index: usize, parent_joint_index: usize) -> Joint {
@@ -364,17 +694,65 @@ fn joint_from_gltf_node(node: &GltfNode, joint_index: usize, parent_joint_index:
     return joint;
 }
 
-pub fn load_skeletal_entity(name: &str, file: &GltfFile) -> SkeletalMesh {
+/// load the skinned node with the given node or mesh name, one skeletal mesh per primitive sharing the rig
+pub fn load_skeletal_entity(name: &str, file: &GltfFile) -> Result<Vec<SkeletalMesh>, GltfError> {
     info!("begin load skinned mesh {}", name);
-    let mut mesh = load_mesh(name, file);
-    if mesh.attribs.joint_indices.len() == 0 {
-        panic!("mesh has no joint indices");
+    let node_index = find_skinned_node(name, file)?;
+    let rig = Rc::new(load_skin_rig(file.nodes[node_index].skin.unwrap(), file)?);
+    return load_skinned_node(node_index, rig, file);
+}
+
+/// load every skinned mesh node in the file, one skeletal mesh per primitive. nodes sharing a skin share its rig
+pub fn load_skeletal_entities(file: &GltfFile) -> Result<Vec<SkeletalMesh>, GltfError> {
+    let mut rigs: HashMap<usize, Rc<Rig>> = HashMap::new();
+    let mut entities = Vec::new();
+    for (node_index, node) in file.nodes.iter().enumerate() {
+        let skin_index = match (node.skin, node.mesh) {
+            (Some(skin_index), Some(_)) => skin_index,
+            _ => continue
+        };
+        if !rigs.contains_key(&skin_index) {
+            rigs.insert(skin_index, Rc::new(load_skin_rig(skin_index, file)?));
+        }
+        entities.extend(load_skinned_node(node_index, rigs[&skin_index].clone(), file)?);
+    }
+    if entities.is_empty() {
+        return Err(GltfError::NoSkin);
+    }
+    info!("loaded {} skinned meshes with {} rigs", entities.len(), rigs.len());
+    return Ok(entities);
+}
+
+fn find_skinned_node(name: &str, file: &GltfFile) -> Result<usize, GltfError> {
+    let mesh_name = |node: &GltfNode| file.meshes.as_ref().unwrap()[node.mesh.unwrap()].name.as_str();
+    let mut skinned_nodes = file.nodes.iter().enumerate().filter(|(_, node)| node.skin.is_some() && node.mesh.is_some()).peekable();
+    if skinned_nodes.peek().is_none() {
+        return Err(GltfError::NoSkin);
     }
-    if mesh.attribs.joint_weights.len() == 0 {
-        panic!("mesh has no joint weights");
+    if let Some((node_index, _)) = skinned_nodes.find(|(_, node)| node.name == name || mesh_name(node) == name) {
+        return Ok(node_index);
     }
+    return Err(GltfError::InvalidMesh { mesh: name.to_owned(), reason: "no skinned node or mesh with this name".to_owned() });
+}
 
-    let rig = load_rig(file);
+/// load every primitive of a skinned node's mesh, posed by rig
+fn load_skinned_node(node_index: usize, rig: Rc<Rig>, file: &GltfFile) -> Result<Vec<SkeletalMesh>, GltfError> {
+    let node = &file.nodes[node_index];
+    let skin_index = node.skin.unwrap();
+    let group = load_mesh_group_at(node.mesh.unwrap(), file)?;
+    if group.primitives.is_empty() {
+        return Err(GltfError::InvalidMesh { mesh: group.name, reason: "mesh has no primitives".to_owned() });
+    }
+    for mesh in group.primitives.iter() {
+        if mesh.attribs.joint_indices.len() == 0 {
+            return Err(GltfError::InvalidMesh { mesh: group.name, reason: "mesh has no joint indices".to_owned() });
+        }
+        if mesh.attribs.joint_weights.len() == 0 {
+            return Err(GltfError::InvalidMesh { mesh: group.name, reason: "mesh has no joint weights".to_owned() });
+        }
+    }
+
+    let skin_joints = &file.skins.as_ref().unwrap()[skin_index].joints;
     info!("rig hierarchy:");
     fn print_rig_node(rig: &Rig, idx: usize) {
         info!("{} children {}", rig.joint_names[idx], rig.joint_children[idx].len());
@@ -382,22 +760,10 @@ pub fn load_skeletal_entity(name: &str, file: &GltfFile) -> SkeletalMesh {
             print_rig_node(rig, *c);
         }
     }
-    print_rig_node(&rig, 0);
-
-    debug!("remap mesh joint_indices");
-    let mut joint_indices = mesh.attribs.joint_indices.clone();
-    for i in 0..joint_indices.len() {
-        let mut remapped_indices = joint_indices[i];
-        fn remap(idx: usize, remap_table: &RigRemapTable) -> usize {
-            return *remap_table.joints.get(&idx).or(Option::Some(&idx)).unwrap();
-        }
-        remapped_indices.x = remap(remapped_indices.x as usize, &rig.remap_table) as i32;
-        remapped_indices.y = remap(remapped_indices.y as usize, &rig.remap_table) as i32;
-        remapped_indices.z = remap(remapped_indices.z as usize, &rig.remap_table) as i32;
-        remapped_indices.w = remap(remapped_indices.w as usize, &rig.remap_table) as i32;
-        joint_indices[i] = remapped_indices;
+    for &root_joint in rig.root_joints.iter() {
+        print_rig_node(&rig, root_joint);
     }
-    mesh.attribs.joint_indices = joint_indices;
+
 
     // build joint local transforms
     let mut joint_local_transforms: Vec<float4x4> = vec![matrix4x4_identity(); rig.joint_count];
@@ -408,18 +774,75 @@ pub fn load_skeletal_entity(name: &str, file: &GltfFile) -> SkeletalMesh {
         joint_transforms[joint_idx] = TRS { translation: joint.translation, rotation: joint.rotation, scale: joint.scale };
     }
 
-    // build inverse binds
+    let inverse_bind_matrices = load_inverse_bind_matrices(skin_index, &rig, &joint_local_transforms, file)?;
+
+    // mesh joint indices index skin.joints, remap them through the joint nodes to rig joints
+    debug!("remap mesh joint_indices");
+    let remap = |idx: i32| -> Result<i32, GltfError> {
+        let node = skin_joints.get(idx as usize).ok_or_else(|| GltfError::InvalidSkin { skin: skin_index,
+            reason: format!("joint index {} is outside the skin's {} joints", idx, skin_joints.len()) })?;
+        let joint = rig.remap_table.joints.get(node).ok_or_else(|| GltfError::InvalidSkin { skin: skin_index,
+            reason: format!("joint index {} (node {}) is not a rig joint", idx, node) })?;
+        return Ok(*joint as i32);
+    };
+    let mut skeletal_meshes = Vec::with_capacity(group.primitives.len());
+    for mut mesh in group.primitives.into_iter() {
+        for joint_indices in mesh.attribs.joint_indices.iter_mut() {
+            joint_indices.x = remap(joint_indices.x)?;
+            joint_indices.y = remap(joint_indices.y)?;
+            joint_indices.z = remap(joint_indices.z)?;
+            joint_indices.w = remap(joint_indices.w)?;
+        }
+        skeletal_meshes.push(SkeletalMesh {
+            name: node.name.clone(),
+            mesh,
+            rig: rig.clone(),
+            joint_local_transforms: joint_local_transforms.clone(),
+            joint_transforms: joint_transforms.clone(),
+            inverse_bind_matrices: inverse_bind_matrices.clone()
+        });
+    }
+    return Ok(skeletal_meshes);
+}
+
+/// the skin's inverseBindMatrices in rig joint order.
+/// without the accessor the mesh is assumed to be bound in the rest pose of the joint nodes
+fn load_inverse_bind_matrices(skin_index: usize, rig: &Rig, joint_local_transforms: &Vec<float4x4>, file: &GltfFile)
+    -> Result<Vec<float4x4>, GltfError> {
+    let skin = &file.skins.as_ref().unwrap()[skin_index];
     let mut inverse_bind_matrices: Vec<float4x4> = vec![matrix4x4_identity(); rig.joint_count];
-    recur_build_inverse_bind_matrices(&rig, &joint_local_transforms, &mut inverse_bind_matrices,
-                                      0, &matrix4x4_identity());
+    let accessor_index = match skin.inverse_bind_matrices {
+        Some(accessor_index) => accessor_index,
+        None => {
+            info!("skin {} has no inverse bind matrices, using the rest pose", skin_index);
+            for (&root_joint, parent_transform) in rig.root_joints.iter().zip(rig.root_parent_transforms.iter()) {
+                recur_build_inverse_bind_matrices(rig, joint_local_transforms, &mut inverse_bind_matrices,
+                                                  root_joint, parent_transform);
+            }
+            return Ok(inverse_bind_matrices);
+        }
+    };
 
-    return SkeletalMesh {
-        mesh,
-        rig,
-        joint_local_transforms,
-        joint_transforms,
-        inverse_bind_matrices
+    let accessor = get_accessor(file, accessor_index)?;
+    if accessor.accessor_type != "MAT4" || accessor.component_type != GltfComponentType::Float as i64 {
+        return Err(GltfError::InvalidSkin { skin: skin_index,
+            reason: format!("inverseBindMatrices accessor is {:?} {}, expected float MAT4", accessor.accessor_type, accessor.component_type) });
+    }
+    let matrices = read_accessor(file, accessor_index, |cursor| {
+        let mut values = [0.0f32; 16];
+        for value in values.iter_mut() {
+            *value = cursor.read_f32::<LittleEndian>().unwrap();
+        }
+        matrix_from_column_major(&values)
+    })?;
+    if matrices.len() < skin.joints.len() {
+        return Err(GltfError::InvalidSkin { skin: skin_index,
+            reason: format!("{} inverse bind matrices for {} joints", matrices.len(), skin.joints.len()) });
+    }
+    for (skin_joint, joint_node) in skin.joints.iter().enumerate() {
+        inverse_bind_matrices[rig.remap_table.joints[joint_node]] = matrices[skin_joint];
     }
+    return Ok(inverse_bind_matrices);
 }
 
 fn recur_build_inverse_bind_matrices(rig: &Rig, joint_local_transforms: &Vec<float4x4>, inverse_bind_matrices: &mut Vec<float4x4>, joint_index: usize, parent: &float4x4) {
@@ -0,0 +1,546 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use math::matrix::{float4x4, matrix4x4_identity};
+use math::vector::float3;
+use serde_json::{json, Value};
+
+use crate::anim::skeletal::{cross, SkeletalAnimation};
+use crate::gltf::{GltfAccessor, GltfAnimation, GltfAnimationChannel, GltfAnimationChannelTarget, GltfAnimationSampler,
+                  GltfBuffer, GltfBufferView, GltfComponentType, GltfError, GltfFile, GltfMesh, GltfMeshPrimitive, GltfNode,
+                  GltfScene, GltfSkin, GLB_CHUNK_BIN, GLB_CHUNK_HEADER_LENGTH, GLB_CHUNK_JSON, GLB_HEADER_LENGTH, GLB_MAGIC};
+use crate::model::{Mesh, MeshIndices, Rig, SkeletalMesh};
+
+const GENERATOR: &str = "rust-oculusvr-mobile-demo gltf writer";
+/// buffer view targets
+const ARRAY_BUFFER: i64 = 34962;
+const ELEMENT_ARRAY_BUFFER: i64 = 34963;
+
+/// builds a gltf document from loaded meshes, skins and animation clips.
+/// all data goes into one buffer, embedded as a data uri (.gltf) or as the binary chunk (.glb).
+/// materials are not written, primitives are exported without one
+pub struct GltfWriter {
+    file: GltfFile,
+    data: Vec<u8>,
+    /// skin index per written rig, meshes sharing a rig share the skin.
+    /// the rigs are held so a later rig can not reuse the address of a dropped one
+    skins: Vec<(Rc<Rig>, usize)>
+}
+
+impl GltfWriter {
+    pub fn new() -> GltfWriter {
+        let file = GltfFile {
+            scene: Some(0),
+            meshes: None,
+            buffer_views: Vec::new(),
+            buffers: Vec::new(),
+            accessors: Vec::new(),
+            scenes: vec![GltfScene { name: None, root_nodes: Some(Vec::new()) }],
+            nodes: Vec::new(),
+            skins: None,
+            animations: None,
+            images: None,
+            materials: None,
+            textures: None,
+            samplers: None,
+            extensions_used: None,
+            extensions_required: None,
+            path: String::new(),
+            decoded_buffers: Vec::new(),
+            decoded_buffer_views: HashMap::new()
+        };
+        return GltfWriter { file, data: Vec::new(), skins: Vec::new() };
+    }
+
+    /// add a mesh instanced by a new scene root node, returns the node index
+    pub fn add_mesh(&mut self, name: &str, mesh: &Mesh) -> Result<usize, GltfError> {
+        let mesh_index = self.write_mesh(name, mesh)?;
+        let mut node = empty_node(name);
+        node.mesh = Some(mesh_index);
+        return Ok(self.add_root_node(node));
+    }
+
+    /// add a skinned mesh with its joint hierarchy and inverse bind matrices, returns the mesh node index
+    pub fn add_skeletal_mesh(&mut self, skeletal_mesh: &SkeletalMesh) -> Result<usize, GltfError> {
+        let written_skin = self.skins.iter().find(|(rig, _)| Rc::ptr_eq(rig, &skeletal_mesh.rig)).map(|&(_, skin_index)| skin_index);
+        let skin_index = match written_skin {
+            Some(skin_index) => skin_index,
+            None => {
+                let skin_index = self.write_skin(&skeletal_mesh.name, &skeletal_mesh.rig, &skeletal_mesh.inverse_bind_matrices)?;
+                self.skins.push((skeletal_mesh.rig.clone(), skin_index));
+                skin_index
+            }
+        };
+        let mesh_index = self.write_mesh(&skeletal_mesh.name, &skeletal_mesh.mesh)?;
+        let mut node = empty_node(&skeletal_mesh.name);
+        node.mesh = Some(mesh_index);
+        node.skin = Some(skin_index);
+        return Ok(self.add_root_node(node));
+    }
+
+    /// add a clip animating the joints of a node returned by add_skeletal_mesh, and the morph weights
+    /// of the written mesh nodes named like its weight tracks. the dense frames are written as linear keyframes
+    pub fn add_animation(&mut self, anim: &SkeletalAnimation, skinned_node: usize) -> Result<(), GltfError> {
+        if anim.num_frames == 0 {
+            warn!("gltf writer: animation {} has no frames, skipped", anim.name);
+            return Ok(());
+        }
+        if anim.additive {
+            warn!("gltf writer: animation {} is additive, its deltas are written as absolute transforms", anim.name);
+        }
+        let invalid = |reason: &str| GltfError::InvalidNode { node: skinned_node, reason: reason.to_owned() };
+        let skin_index = self.file.nodes.get(skinned_node).ok_or_else(|| invalid("does not exist"))?
+            .skin.ok_or_else(|| invalid("has no skin to animate"))?;
+        let joint_nodes = self.file.skins.as_ref().unwrap()[skin_index].joints.clone();
+        if anim.joints.len() > joint_nodes.len() {
+            return Err(invalid(&format!("skin has {} joints, animation {} has {}", joint_nodes.len(), anim.name, anim.joints.len())));
+        }
+
+        let time_step = 1.0 / anim.sample_rate;
+        let mut times: Vec<f32> = (0..anim.num_frames).map(|frame| time_step * (frame as f32)).collect();
+        // the loader samples frames up to max_time, a closing keyframe keeps the frame count on reload
+        if anim.max_time > times[times.len() - 1] {
+            times.push(anim.max_time);
+        }
+        let last_frame = anim.num_frames - 1;
+        let keyframes: Vec<usize> = (0..times.len()).map(|keyframe| keyframe.min(last_frame)).collect();
+        let input = self.write_floats(&times, "SCALAR", None)?;
+
+        let mut channels: Vec<GltfAnimationChannel> = Vec::new();
+        let mut samplers: Vec<GltfAnimationSampler> = Vec::new();
+        let mut add_channel = |samplers: &mut Vec<GltfAnimationSampler>, output: usize, node: usize, path: &str| {
+            channels.push(GltfAnimationChannel {
+                sampler: samplers.len(),
+                target: GltfAnimationChannelTarget { node, path: path.to_owned() }
+            });
+            samplers.push(GltfAnimationSampler { input, interpolation: "LINEAR".to_owned(), output });
+        };
+        for (joint_index, frames) in anim.joints.iter().enumerate() {
+            let translations: Vec<f32> = keyframes.iter().flat_map(|&k| float3_components(&frames[k].translation)).collect();
+            let rotations: Vec<f32> = keyframes.iter()
+                .flat_map(|&k| { let r = &frames[k].rotation; vec![r.x, r.y, r.z, r.w] })
+                .collect();
+            let scales: Vec<f32> = keyframes.iter().flat_map(|&k| float3_components(&frames[k].scale)).collect();
+            let translation_output = self.write_floats(&translations, "VEC3", None)?;
+            let rotation_output = self.write_floats(&rotations, "VEC4", None)?;
+            let scale_output = self.write_floats(&scales, "VEC3", None)?;
+            add_channel(&mut samplers, translation_output, joint_nodes[joint_index], "translation");
+            add_channel(&mut samplers, rotation_output, joint_nodes[joint_index], "rotation");
+            add_channel(&mut samplers, scale_output, joint_nodes[joint_index], "scale");
+        }
+        for track in anim.morph_weights.iter() {
+            let node = if self.file.nodes[skinned_node].name == track.node {
+                Some(skinned_node)
+            } else {
+                self.file.nodes.iter().position(|node| node.mesh.is_some() && node.name == track.node)
+            };
+            let node = match node {
+                Some(node) => node,
+                None => {
+                    warn!("gltf writer: animation {} weights node {} was not written, skipped", anim.name, track.node);
+                    continue;
+                }
+            };
+            let weights: Vec<f32> = keyframes.iter().flat_map(|&k| track.frames[k].iter().cloned()).collect();
+            let weights_output = self.write_floats(&weights, "SCALAR", None)?;
+            add_channel(&mut samplers, weights_output, node, "weights");
+        }
+
+        self.file.animations.get_or_insert_with(Vec::new).push(GltfAnimation {
+            name: anim.name.clone(),
+            channels,
+            samplers
+        });
+        return Ok(());
+    }
+
+    /// gltf json with the buffer embedded as a base64 data uri
+    pub fn to_gltf(&self) -> Result<String, GltfError> {
+        let uri = format!("data:application/octet-stream;base64,{}", base64::encode(&self.data));
+        return serde_json::to_string_pretty(&self.to_json(Some(uri))?).map_err(GltfError::Json);
+    }
+
+    /// glb container, the buffer is stored in the binary chunk
+    pub fn to_glb(&self) -> Result<Vec<u8>, GltfError> {
+        let mut json = serde_json::to_vec(&self.to_json(None)?).map_err(GltfError::Json)?;
+        // chunks are 4 byte aligned, json is padded with spaces and binary data with zeros
+        while json.len() % 4 != 0 {
+            json.push(b' ');
+        }
+        let mut bin = self.data.clone();
+        while bin.len() % 4 != 0 {
+            bin.push(0);
+        }
+        let mut length = GLB_HEADER_LENGTH + GLB_CHUNK_HEADER_LENGTH + json.len();
+        if !bin.is_empty() {
+            length += GLB_CHUNK_HEADER_LENGTH + bin.len();
+        }
+
+        let mut glb: Vec<u8> = Vec::with_capacity(length);
+        glb.write_u32::<LittleEndian>(GLB_MAGIC).unwrap();
+        glb.write_u32::<LittleEndian>(2).unwrap();
+        glb.write_u32::<LittleEndian>(length as u32).unwrap();
+        glb.write_u32::<LittleEndian>(json.len() as u32).unwrap();
+        glb.write_u32::<LittleEndian>(GLB_CHUNK_JSON).unwrap();
+        glb.extend(json);
+        if !bin.is_empty() {
+            glb.write_u32::<LittleEndian>(bin.len() as u32).unwrap();
+            glb.write_u32::<LittleEndian>(GLB_CHUNK_BIN).unwrap();
+            glb.extend(bin);
+        }
+        return Ok(glb);
+    }
+
+    /// write a .glb, or a self contained .gltf for any other extension
+    pub fn save(&self, path: &Path) -> Result<(), GltfError> {
+        let is_glb = path.extension().map_or(false, |extension| extension.eq_ignore_ascii_case("glb"));
+        let data = if is_glb { self.to_glb()? } else { self.to_gltf()?.into_bytes() };
+        info!("gltf writer: saving {} ({} bytes)", path.display(), data.len());
+        return fs::write(path, data).map_err(|e| GltfError::Write(path.display().to_string(), e));
+    }
+
+    /// the document as json, without null fields or empty top level arrays
+    fn to_json(&self, buffer_uri: Option<String>) -> Result<Value, GltfError> {
+        let mut json = serde_json::to_value(&self.file).map_err(GltfError::Json)?;
+        if !self.data.is_empty() {
+            let buffer = GltfBuffer { byte_length: self.data.len(), uri: buffer_uri, extensions: None };
+            json["buffers"] = serde_json::to_value(vec![buffer]).map_err(GltfError::Json)?;
+        }
+        strip_nulls(&mut json);
+        if let Value::Object(fields) = &mut json {
+            // gltf arrays need at least one element when present
+            fields.retain(|_, value| value.as_array().map_or(true, |array| !array.is_empty()));
+        }
+        json["asset"] = json!({ "version": "2.0", "generator": GENERATOR });
+        return Ok(json);
+    }
+
+    fn add_root_node(&mut self, node: GltfNode) -> usize {
+        let node_index = self.file.nodes.len();
+        self.file.nodes.push(node);
+        self.file.scenes[0].root_nodes.as_mut().unwrap().push(node_index);
+        return node_index;
+    }
+
+    /// joint nodes in rig order so mesh joint indices index skin.joints unchanged
+    fn write_skin(&mut self, name: &str, rig: &Rig, inverse_bind_matrices: &Vec<float4x4>) -> Result<usize, GltfError> {
+        let first_joint = self.file.nodes.len();
+        for joint_index in 0..rig.joint_count {
+            let joint = &rig.joint_transforms[joint_index];
+            let mut node = empty_node(&rig.joint_names[joint_index]);
+            node.translation = Some(float3_components(&joint.translation));
+            node.rotation = Some(vec![joint.rotation.x, joint.rotation.y, joint.rotation.z, joint.rotation.w]);
+            node.scale = Some(float3_components(&joint.scale));
+            let children = &rig.joint_children[joint_index];
+            if !children.is_empty() {
+                node.children = Some(children.iter().map(|child| first_joint + child).collect());
+            }
+            self.file.nodes.push(node);
+        }
+        for (&root_joint, parent_transform) in rig.root_joints.iter().zip(rig.root_parent_transforms.iter()) {
+            let parent_matrix = column_major(parent_transform);
+            if parent_matrix == column_major(&matrix4x4_identity()) {
+                self.file.scenes[0].root_nodes.as_mut().unwrap().push(first_joint + root_joint);
+                continue;
+            }
+            // keep the transform above the skeleton, e.g. a scaled armature
+            let mut parent = empty_node(&format!("{}_parent", rig.joint_names[root_joint]));
+            parent.matrix = Some(parent_matrix);
+            parent.children = Some(vec![first_joint + root_joint]);
+            self.add_root_node(parent);
+        }
+
+        let matrices: Vec<f32> = inverse_bind_matrices.iter().flat_map(|m| column_major(m)).collect();
+        let inverse_bind_accessor = self.write_floats(&matrices, "MAT4", None)?;
+        let skins = self.file.skins.get_or_insert_with(Vec::new);
+        skins.push(GltfSkin {
+            name: name.to_owned(),
+            inverse_bind_matrices: Some(inverse_bind_accessor),
+            joints: (first_joint..first_joint + rig.joint_count).collect(),
+            skeleton: None
+        });
+        return Ok(skins.len() - 1);
+    }
+
+    fn write_mesh(&mut self, name: &str, mesh: &Mesh) -> Result<usize, GltfError> {
+        let attribs = &mesh.attribs;
+        let mut attributes: HashMap<String, usize> = HashMap::new();
+        if !attribs.position.is_empty() {
+            let values: Vec<f32> = attribs.position.iter().flat_map(float3_components).collect();
+            attributes.insert("POSITION".to_owned(), self.write_floats(&values, "VEC3", Some(ARRAY_BUFFER))?);
+        }
+        if !attribs.normal.is_empty() {
+            let values: Vec<f32> = attribs.normal.iter().flat_map(float3_components).collect();
+            attributes.insert("NORMAL".to_owned(), self.write_floats(&values, "VEC3", Some(ARRAY_BUFFER))?);
+        }
+        if !attribs.tangent.is_empty() {
+            // handedness (w) is recovered from the binormal, binormal = cross(normal, tangent) * w
+            let values: Vec<f32> = (0..attribs.tangent.len()).flat_map(|i| {
+                let t = &attribs.tangent[i];
+                let w = match (attribs.normal.get(i), attribs.binormal.get(i)) {
+                    (Some(n), Some(b)) => {
+                        let c = cross(n, t);
+                        if c.x * b.x + c.y * b.y + c.z * b.z < 0.0 { -1.0 } else { 1.0 }
+                    },
+                    _ => 1.0
+                };
+                vec![t.x, t.y, t.z, w]
+            }).collect();
+            attributes.insert("TANGENT".to_owned(), self.write_floats(&values, "VEC4", Some(ARRAY_BUFFER))?);
+        }
+        if !attribs.color.is_empty() {
+            let values: Vec<f32> = attribs.color.iter().flat_map(|c| vec![c.x, c.y, c.z, c.w]).collect();
+            attributes.insert("COLOR_0".to_owned(), self.write_floats(&values, "VEC4", Some(ARRAY_BUFFER))?);
+        }
+        for (attr_key, uvs) in [("TEXCOORD_0", &attribs.uv0), ("TEXCOORD_1", &attribs.uv1)].iter() {
+            if !uvs.is_empty() {
+                let values: Vec<f32> = uvs.iter().flat_map(|uv| vec![uv.x, uv.y]).collect();
+                attributes.insert(attr_key.to_string(), self.write_floats(&values, "VEC2", Some(ARRAY_BUFFER))?);
+            }
+        }
+        if !attribs.joint_indices.is_empty() {
+            let mut bytes: Vec<u8> = Vec::with_capacity(attribs.joint_indices.len() * 8);
+            for joints in attribs.joint_indices.iter() {
+                for &joint in [joints.x, joints.y, joints.z, joints.w].iter() {
+                    // JOINTS_0 is unsigned byte or unsigned short only
+                    if joint < 0 || joint > u16::MAX as i32 {
+                        return Err(GltfError::InvalidMesh { mesh: name.to_owned(), reason: format!("joint index {} does not fit JOINTS_0", joint) });
+                    }
+                    bytes.write_u16::<LittleEndian>(joint as u16).unwrap();
+                }
+            }
+            let accessor = self.write_accessor(bytes, attribs.joint_indices.len(), GltfComponentType::UnsignedShort,
+                                               "VEC4", Some(ARRAY_BUFFER), None);
+            attributes.insert("JOINTS_0".to_owned(), accessor);
+        }
+        if !attribs.joint_weights.is_empty() {
+            let values: Vec<f32> = attribs.joint_weights.iter().flat_map(|w| vec![w.x, w.y, w.z, w.w]).collect();
+            attributes.insert("WEIGHTS_0".to_owned(), self.write_floats(&values, "VEC4", Some(ARRAY_BUFFER))?);
+        }
+
+        let indices = match &mesh.indices {
+            MeshIndices::None => None,
+            MeshIndices::U8(indices) => Some(self.write_accessor(indices.clone(), indices.len(),
+                GltfComponentType::UnsignedByte, "SCALAR", Some(ELEMENT_ARRAY_BUFFER), None)),
+            MeshIndices::U16(indices) => {
+                let mut bytes: Vec<u8> = Vec::with_capacity(indices.len() * 2);
+                indices.iter().for_each(|&index| bytes.write_u16::<LittleEndian>(index).unwrap());
+                Some(self.write_accessor(bytes, indices.len(), GltfComponentType::UnsignedShort, "SCALAR", Some(ELEMENT_ARRAY_BUFFER), None))
+            },
+            MeshIndices::U32(indices) => {
+                let mut bytes: Vec<u8> = Vec::with_capacity(indices.len() * 4);
+                indices.iter().for_each(|&index| bytes.write_u32::<LittleEndian>(index).unwrap());
+                Some(self.write_accessor(bytes, indices.len(), GltfComponentType::UnsignedInt, "SCALAR", Some(ELEMENT_ARRAY_BUFFER), None))
+            }
+        };
+
+        let mut targets: Vec<HashMap<String, usize>> = Vec::new();
+        for target in mesh.targets.iter() {
+            let mut target_attributes: HashMap<String, usize> = HashMap::new();
+            for (attr_key, deltas) in [("POSITION", &target.position), ("NORMAL", &target.normal), ("TANGENT", &target.tangent)].iter() {
+                if !deltas.is_empty() {
+                    let values: Vec<f32> = deltas.iter().flat_map(float3_components).collect();
+                    target_attributes.insert(attr_key.to_string(), self.write_floats(&values, "VEC3", Some(ARRAY_BUFFER))?);
+                }
+            }
+            targets.push(target_attributes);
+        }
+
+        let meshes = self.file.meshes.get_or_insert_with(Vec::new);
+        meshes.push(GltfMesh {
+            name: name.to_owned(),
+            primitives: vec![GltfMeshPrimitive {
+                attributes,
+                indices,
+                material: None,
+                mode: Some(mesh.mode as i64),
+                targets: if targets.is_empty() { None } else { Some(targets) }
+            }],
+            weights: if mesh.targets.is_empty() { None } else { Some(mesh.weights.clone()) }
+        });
+        return Ok(meshes.len() - 1);
+    }
+
+    /// float accessor with min/max bounds, components per element follow accessor_type
+    fn write_floats(&mut self, values: &Vec<f32>, accessor_type: &str, target: Option<i64>) -> Result<usize, GltfError> {
+        let components = match accessor_type {
+            "SCALAR" => 1,
+            "VEC2" => 2,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            "MAT4" => 16,
+            _ => return Err(GltfError::InvalidAccessor {
+                accessor: self.file.accessors.len(),
+                reason: format!("unsupported float accessor type {}", accessor_type)
+            })
+        };
+        let mut min = vec![std::f32::MAX; components];
+        let mut max = vec![std::f32::MIN; components];
+        let mut bytes: Vec<u8> = Vec::with_capacity(values.len() * 4);
+        for (i, &value) in values.iter().enumerate() {
+            min[i % components] = min[i % components].min(value);
+            max[i % components] = max[i % components].max(value);
+            bytes.write_f32::<LittleEndian>(value).unwrap();
+        }
+        let bounds = if values.is_empty() { None } else { Some((min, max)) };
+        return Ok(self.write_accessor(bytes, values.len() / components, GltfComponentType::Float, accessor_type, target, bounds));
+    }
+
+    /// append bytes as a new buffer view with a tightly packed accessor, returns the accessor index
+    fn write_accessor(&mut self, bytes: Vec<u8>, count: usize, component_type: GltfComponentType, accessor_type: &str,
+                      target: Option<i64>, bounds: Option<(Vec<f32>, Vec<f32>)>) -> usize {
+        // 4 byte alignment satisfies every component type
+        while self.data.len() % 4 != 0 {
+            self.data.push(0);
+        }
+        self.file.buffer_views.push(GltfBufferView {
+            buffer: 0,
+            byte_length: bytes.len(),
+            byte_offset: self.data.len(),
+            byte_stride: None,
+            target,
+            extensions: None
+        });
+        self.data.extend(bytes);
+        let (min, max) = match bounds {
+            Some((min, max)) => (Some(min), Some(max)),
+            None => (None, None)
+        };
+        self.file.accessors.push(GltfAccessor {
+            buffer_view: Some(self.file.buffer_views.len() - 1),
+            byte_offset: 0,
+            component_type: component_type as i64,
+            normalized: false,
+            accessor_type: accessor_type.to_owned(),
+            count: count as i64,
+            min,
+            max,
+            sparse: None
+        });
+        return self.file.accessors.len() - 1;
+    }
+}
+
+fn empty_node(name: &str) -> GltfNode {
+    return GltfNode {
+        name: name.to_owned(),
+        children: None,
+        translation: None,
+        rotation: None,
+        scale: None,
+        matrix: None,
+        mesh: None,
+        skin: None
+    };
+}
+
+fn float3_components(v: &float3) -> Vec<f32> {
+    return vec![v.x, v.y, v.z];
+}
+
+/// float4x4 is row-major, gltf matrices are column-major
+fn column_major(m: &float4x4) -> Vec<f32> {
+    return (0..4).flat_map(|column| (0..4).map(move |row| m.m[row][column])).collect();
+}
+
+/// optional fields serialize as null, gltf expects them to be absent
+fn strip_nulls(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            fields.retain(|_, field| !field.is_null());
+            fields.values_mut().for_each(strip_nulls);
+        },
+        Value::Array(elements) => elements.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_write_round_trip() {
+    use math::quaternion::quaternion;
+    use math::vector::{float2, float4, int4};
+    use crate::anim::skeletal::TRS;
+    use crate::gltf::load_gltf_from_slice;
+    use crate::gltf::skeletal::{load_animations, load_skeletal_entity};
+    use crate::model::{Joint, PrimitiveMode, RigRemapTable};
+    use crate::render::gl_geometry::VertexAttribs;
+
+    let joint = |index: usize, translation: float3| Joint {
+        index, parent_index: 0, translation, rotation: quaternion::identity(), scale: float3::one()
+    };
+    let rig = Rig {
+        joint_transforms: vec![joint(0, float3::zero()), joint(1, float3::new(0.0, 1.0, 0.0))],
+        joint_count: 2,
+        joint_names: vec!["root".to_owned(), "tip".to_owned()],
+        joint_children: vec![vec![1], vec![]],
+        joint_parents: vec![0, 0],
+        root_joints: vec![0],
+        root_parent_transforms: vec![matrix4x4_identity()],
+        remap_table: RigRemapTable { joints: HashMap::new() }
+    };
+    let mesh = Mesh {
+        attribs: VertexAttribs {
+            position: vec![float3::zero(), float3::new(1.0, 0.0, 0.0), float3::new(0.0, 1.0, 0.0)],
+            normal: vec![float3::new(0.0, 0.0, 1.0); 3],
+            tangent: Vec::new(),
+            binormal: Vec::new(),
+            color: Vec::new(),
+            uv0: vec![float2::new(0.0, 0.0), float2::new(1.0, 0.0), float2::new(0.0, 1.0)],
+            uv1: Vec::new(),
+            joint_indices: vec![int4::new(0, 0, 0, 0), int4::new(0, 0, 0, 0), int4::new(1, 0, 0, 0)],
+            joint_weights: vec![float4::new(1.0, 0.0, 0.0, 0.0); 3]
+        },
+        indices: MeshIndices::U16(vec![0, 1, 2]),
+        mode: PrimitiveMode::Triangles,
+        material: None,
+        targets: Vec::new(),
+        weights: Vec::new(),
+        dequantization: None
+    };
+    let skeletal_mesh = SkeletalMesh {
+        name: "triangle".to_owned(),
+        mesh,
+        rig: Rc::new(rig),
+        joint_transforms: vec![TRS::default(); 2],
+        joint_local_transforms: vec![matrix4x4_identity(); 2],
+        inverse_bind_matrices: vec![matrix4x4_identity(); 2]
+    };
+    let (sample_rate, max_time) = (30.0f32, 0.1f32);
+    let lift = |y: f32| TRS { translation: float3::new(0.0, y, 0.0), rotation: quaternion::identity(), scale: float3::one() };
+    let anim = SkeletalAnimation {
+        name: "lift".to_owned(),
+        sample_rate,
+        num_frames: (max_time / (1.0 / sample_rate)).floor() as usize,
+        min_time: 0.0,
+        max_time,
+        joints: vec![vec![lift(0.0), lift(1.0), lift(2.0)], vec![lift(1.0); 3]],
+        morph_weights: Vec::new(),
+        additive: false
+    };
+
+    let mut writer = GltfWriter::new();
+    let node = writer.add_skeletal_mesh(&skeletal_mesh).unwrap();
+    writer.add_animation(&anim, node).unwrap();
+    for data in vec![writer.to_glb().unwrap(), writer.to_gltf().unwrap().into_bytes()] {
+        let file = load_gltf_from_slice(&data, "round_trip").unwrap();
+        let loaded = load_skeletal_entity("triangle", &file).unwrap().remove(0);
+        let positions = |mesh: &Mesh| mesh.attribs.position.iter().map(|p| (p.x, p.y, p.z)).collect::<Vec<_>>();
+        let uvs = |mesh: &Mesh| mesh.attribs.uv0.iter().map(|uv| (uv.x, uv.y)).collect::<Vec<_>>();
+        assert_eq!(positions(&skeletal_mesh.mesh), positions(&loaded.mesh));
+        assert_eq!(uvs(&skeletal_mesh.mesh), uvs(&loaded.mesh));
+        assert_eq!(vec![0, 0, 1], loaded.mesh.attribs.joint_indices.iter().map(|j| j.x).collect::<Vec<_>>());
+        assert_eq!(vec![0, 1, 2], (0..3).map(|i| loaded.mesh.indices.get(i)).collect::<Vec<_>>());
+        assert_eq!(skeletal_mesh.rig.joint_names, loaded.rig.joint_names);
+
+        let library = load_animations(&loaded, &file).unwrap();
+        let loaded_anim = library.get("lift").unwrap();
+        assert_eq!(anim.num_frames, loaded_anim.num_frames);
+        for frame in 0..anim.num_frames {
+            assert_eq!(anim.joints[0][frame].translation.y, loaded_anim.joints[0][frame].translation.y);
+        }
+    }
+}
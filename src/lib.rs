@@ -28,9 +28,9 @@ use ovr_mobile_sys::ovrTextureType_::VRAPI_TEXTURE_TYPE_2D;
 
 use crate::graphics::*;
 use crate::input::DeviceInput;
-use crate::model::SkeletalMesh;
+use crate::model::{Mesh, SkeletalMesh, TextureSampler};
 use crate::render::gl_buffer::GlBuffer;
-use crate::render::gl_geometry::{GlGeometry, make_geometry};
+use crate::render::gl_geometry::{GlGeometry, GlGeometrySet, GlSceneGeometry, VertexAttribs, draw_geometry, make_geometry, make_geometry_set, make_scene_geometry, update_morphed_geometry};
 use crate::render::gl_geometry;
 use crate::shader::ShaderProgram;
 
@@ -39,7 +39,7 @@ use ndk_glue::{Event, native_activity, native_window, poll_events};
 use ndk_sys::JNINativeInterface;
 use crate::anim::composer::SkeletalComposer;
 use std::cell::Cell;
-use crate::anim::layer::{SkeletalLayer, SkeletalLayerSpec};
+use crate::anim::layer::{BlendMode, SkeletalLayer, SkeletalLayerSpec};
 
 mod graphics;
 mod vrapi;
@@ -249,9 +249,22 @@ pub fn main() {
             }
         }
 
-        app_state.scene.mob_composer.update(time.delta_time);
-        let anim_matrices = app_state.scene.mob_composer.sample(&app_state.scene.mob_skinned_mesh);
-        app_state.scene.mob_jointbuf.update((anim_matrices.len() * mem::size_of::<float4x4>()) as isize, anim_matrices.as_ptr() as *const _ as *const u8);
+        let scene = &mut app_state.scene;
+        if let (Some(mob_skinned_meshes), Some(mob_composer)) = (scene.mob_skinned_meshes.as_ref(), scene.mob_composer.as_mut()) {
+            // the primitives share one rig, pose it once
+            mob_composer.update(time.delta_time);
+            let anim_matrices = mob_composer.sample(&mob_skinned_meshes[0]);
+            scene.mob_jointbuf.update((anim_matrices.len() * mem::size_of::<float4x4>()) as isize, anim_matrices.as_ptr() as *const _ as *const u8);
+
+            // morph on the cpu and re-upload the morphed attributes
+            for (primitive, skinned_mesh) in mob_skinned_meshes.iter().enumerate() {
+                if let Some(morph_attribs) = scene.mob_morph_attribs[primitive].as_mut() {
+                    let weights = mob_composer.sample_morph_weights(skinned_mesh);
+                    skinned_mesh.mesh.apply_morph_targets(&weights, morph_attribs);
+                    update_morphed_geometry(&scene.mob[primitive], morph_attribs);
+                }
+            }
+        }
 
         // Advance the simulation based on the elapsed time since start of loop till predicted display time.
         //unsafe { ovr::ovrSimulation_Advance( &appState.Simulation, predictedDisplayTime - startTime ) };
@@ -306,13 +319,18 @@ pub struct OvrScene {
     pub random: i64,
     pub shader_programs: [ShaderProgram; shader::NUM_PROGRAMS],
     pub scene_matrices: GlBuffer,
-    pub tabletop: GlGeometry,
-    pub mob_skinned_mesh: SkeletalMesh,
-    pub mob: GlGeometry,
+    pub tabletop: GlSceneGeometry,
+    /// one per bear mesh primitive, None when the bear failed to load, it is neither animated nor drawn
+    pub mob_skinned_meshes: Option<Vec<SkeletalMesh>>,
+    /// geometry per bear primitive
+    pub mob: Vec<GlGeometry>,
     pub mob_texture: GLuint,
     pub mob_jointbuf: GlBuffer,
-    pub mob_composer: SkeletalComposer,
-    pub controller: GlGeometry,
+    /// None until the bear and its clips are loaded
+    pub mob_composer: Option<SkeletalComposer>,
+    /// scratch attribs for cpu morphing per bear primitive, None for primitives without morph targets
+    pub mob_morph_attribs: Vec<Option<VertexAttribs>>,
+    pub controller: GlGeometrySet,
     pub controller_orientation: ovrQuatf,
     pub interface_layer_cylinder_width: i32,
     pub interface_layer_cylinder_height: i32,
@@ -327,6 +345,67 @@ pub fn ovr_scene_clear(scene: &mut OvrScene) {
     // clear geo
 }
 
+/// bear clip played on scene start
+const BEAR_CLIP: &str = "attack";
+
+/// load a gltf file from the asset root
+fn load_gltf_asset(path: &str) -> Result<gltf::GltfFile, gltf::GltfError> {
+    let mut asset = assets::load_asset(path).ok_or_else(|| gltf::GltfError::MissingAsset(path.to_owned()))?;
+    return gltf::load_gltf(&mut asset);
+}
+
+/// load a named mesh as gl geometry, empty geometry (nothing drawn) if the asset fails to load
+fn load_geometry_set(path: &str, mesh_name: &str) -> GlGeometrySet {
+    let group = load_gltf_asset(path).and_then(|file| gltf::mesh::load_mesh_group(mesh_name, &file));
+    return match group {
+        Ok(group) => make_geometry_set(&group),
+        Err(e) => {
+            error!("failed to load {} from {}: {}", mesh_name, path, e);
+            GlGeometrySet { submeshes: Vec::new(), materials: Vec::new() }
+        }
+    };
+}
+
+/// png bytes and sampler of a mesh's base color texture, None if its material has no png base color texture
+fn load_base_color_png(mesh: &Mesh, file: &gltf::GltfFile) -> Option<(Vec<u8>, TextureSampler)> {
+    let material = match gltf::material::load_material_or_default(mesh.material, file) {
+        Ok(material) => material,
+        Err(e) => {
+            warn!("failed to load material: {}", e);
+            return None;
+        }
+    };
+    let texture = material.base_color_texture?;
+    let mime_type = gltf::material::image_mime_type(texture.image, file);
+    if mime_type.as_ref().map(|m| m.as_str()) != Some("image/png") {
+        warn!("base color image {} is {:?}, only png is supported", texture.image, mime_type);
+        return None;
+    }
+    return match gltf::material::load_texture_image(&texture, file) {
+        Ok(data) => Some((data, texture.sampler)),
+        Err(e) => {
+            warn!("failed to load base color image {}: {}", texture.image, e);
+            None
+        }
+    };
+}
+
+/// load the default scene of a gltf file as gl geometry, empty if the asset fails to load
+fn load_scene_geometry(path: &str) -> GlSceneGeometry {
+    let scene = load_gltf_asset(path).and_then(|file| {
+        let groups = gltf::mesh::load_mesh_groups(&file)?;
+        let instances = gltf::scene::import_scene(None, &file)?;
+        Ok(make_scene_geometry(&groups, &instances))
+    });
+    return match scene {
+        Ok(scene) => scene,
+        Err(e) => {
+            error!("failed to load scene {}: {}", path, e);
+            GlSceneGeometry { meshes: Vec::new(), instances: Vec::new() }
+        }
+    };
+}
+
 fn ovr_scene_create(scene: &mut OvrScene, extns: &GlExtensions, multiview: bool) {
     println!("ovr_scene_create");
     scene.created_scene = true;
@@ -345,24 +424,37 @@ fn ovr_scene_create(scene: &mut OvrScene, extns: &GlExtensions, multiview: bool)
     // 2 view matrices + 2 projection matrices
     scene.scene_matrices = GlBuffer::create((mem::size_of::<ovr::ovrMatrix4f>() * 4) as isize, std::ptr::null());
 
-    let tabletop_gltf_file = gltf::load_gltf(&mut assets::load_asset("tabletop.gltf").unwrap());
-    let tabletop_mesh = gltf::mesh::load_mesh("tabletop", &tabletop_gltf_file);
-    let controller_gltf_file = gltf::load_gltf(&mut assets::load_asset("resources/controller_gearvr.gltf").unwrap());
-    let controller_mesh = gltf::mesh::load_mesh("controller_gearvr", &controller_gltf_file);
-
-    scene.tabletop = make_geometry(&tabletop_mesh.attribs, &tabletop_mesh.indices);
-    scene.controller = make_geometry(&controller_mesh.attribs, &controller_mesh.indices);
+    scene.tabletop = load_scene_geometry("tabletop.gltf");
+    scene.controller = load_geometry_set("resources/controller_gearvr.gltf", "controller_gearvr");
 
     println!("read mob mesh");
-    let mut mob_asset = assets::load_asset("resources/mesh_brownbear_v2.gltf").unwrap();
-    let mob_gltf_file = gltf::load_gltf(&mut mob_asset);
-    scene.mob_skinned_mesh = gltf::skeletal::load_skeletal_entity("bear", &mob_gltf_file);
-    scene.mob = make_geometry(&scene.mob_skinned_mesh.mesh.attribs, &scene.mob_skinned_mesh.mesh.indices);
-
-    info!("read mob animations");
-    let mut mob_anim_asset = assets::load_asset("resources/anim_bear_attack.gltf").unwrap();
-    let mob_anim_gltf_file = gltf::load_gltf(&mut mob_anim_asset);
-    let bear_anim = gltf::skeletal::load_animations(&scene.mob_skinned_mesh, &mob_anim_gltf_file);
+    let bear_assets = load_gltf_asset("resources/mesh_brownbear_v2.gltf").and_then(|file| {
+        let skinned_meshes = gltf::skeletal::load_skeletal_entity("bear", &file)?;
+        // the bear is drawn with a single texture, the first primitive's
+        let texture = load_base_color_png(&skinned_meshes[0].mesh, &file);
+        info!("read mob animations");
+        let anim_file = load_gltf_asset("resources/anim_bear_attack.gltf")?;
+        let library = gltf::skeletal::load_animations(&skinned_meshes[0], &anim_file)?;
+        Ok((skinned_meshes, library, texture))
+    });
+    let mut bear_texture = None;
+    let bear_library = match bear_assets {
+        Ok((skinned_meshes, library, texture)) => {
+            bear_texture = texture;
+            scene.mob = skinned_meshes.iter()
+                .map(|skinned_mesh| make_geometry(&skinned_mesh.mesh.attribs, &skinned_mesh.mesh.indices, skinned_mesh.mesh.mode))
+                .collect();
+            scene.mob_morph_attribs = skinned_meshes.iter()
+                .map(|skinned_mesh| if skinned_mesh.mesh.targets.is_empty() { None } else { Some(skinned_mesh.mesh.attribs.clone()) })
+                .collect();
+            scene.mob_skinned_meshes = Some(skinned_meshes);
+            Some(library)
+        },
+        Err(e) => {
+            error!("failed to load bear, skipping: {}", e);
+            None
+        }
+    };
 
     scene.mob_jointbuf = GlBuffer::create((gl_geometry::MAX_JOINTS
                         * mem::size_of::<float4x4>() as i32) as isize, std::ptr::null());
@@ -372,16 +464,30 @@ fn ovr_scene_create(scene: &mut OvrScene, extns: &GlExtensions, multiview: bool)
         std::ptr::copy(joints.as_ptr() as *const _ as *const u8, joint_ptr, (joints.len() * mem::size_of::<float4x4>()) as usize);
     }
     scene.mob_jointbuf.unmap_buffer();
-    let composer = SkeletalComposer::new(1.0,
-                                         vec![SkeletalLayer { spec: SkeletalLayerSpec {
-                                                                            loopanim: true,
-                                                                             playback_speed: Cell::new(1.0)},
-                                             anim: bear_anim }]);
-    scene.mob_composer = composer;
+    if let Some(bear_library) = bear_library {
+        // play the attack clip, or the first clip of the file if it is named differently
+        let bear_anim = bear_library.get(BEAR_CLIP)
+            .or_else(|| bear_library.get(&bear_library.names()[0]))
+            .unwrap();
+        let layers = vec![SkeletalLayer::new(SkeletalLayerSpec {
+                                                 loopanim: true,
+                                                 playback_speed: Cell::new(1.0),
+                                                 weight: Cell::new(1.0),
+                                                 blend_mode: Cell::new(BlendMode::Override),
+                                                 mask: None},
+                                             bear_anim)];
+        let composer = SkeletalComposer::new(1.0, bear_library, layers);
+        scene.mob_composer = Some(composer);
+    }
 
     println!("read mob texture");
-    let mut mob_texture_asset = assets::load_asset("resources/tex_brownbear_color.png").unwrap();
-    let mob_texture_decoder = png::Decoder::new((&mut mob_texture_asset).get_buffer().unwrap());
+    // the material's base color texture, or the bundled bear texture for models without one
+    let (mob_texture_png, mob_texture_sampler) = bear_texture.unwrap_or_else(|| {
+        let mut mob_texture_asset = assets::load_asset("resources/tex_brownbear_color.png").unwrap();
+        let sampler = TextureSampler { mag_filter: None, min_filter: None, wrap_s: GL_REPEAT, wrap_t: GL_REPEAT };
+        ((&mut mob_texture_asset).get_buffer().unwrap().to_vec(), sampler)
+    });
+    let mob_texture_decoder = png::Decoder::new(mob_texture_png.as_slice());
     let (info, mut reader) = mob_texture_decoder.read_info().unwrap();
     let mut texbuf = vec![0; info.buffer_size()];
     reader.next_frame(&mut texbuf).unwrap();
@@ -392,10 +498,14 @@ fn ovr_scene_create(scene: &mut OvrScene, extns: &GlExtensions, multiview: bool)
         glGenTextures(1, &mut mobtexture);
         glBindTexture(GL_TEXTURE_2D, mobtexture);
 
-        glTexImage2D(GL_TEXTURE_2D, 0, GL_RGB as i32, info.width as i32, info.height as i32,
-                     0, GL_RGB, GL_UNSIGNED_BYTE, texbuf.as_mut_ptr() as *const _ as *const c_void);
-        glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_MAG_FILTER, GL_LINEAR as i32);
-        glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_MIN_FILTER, GL_LINEAR_MIPMAP_LINEAR as i32);
+        let format = if info.color_type == png::ColorType::RGBA { GL_RGBA } else { GL_RGB };
+        glTexImage2D(GL_TEXTURE_2D, 0, format as i32, info.width as i32, info.height as i32,
+                     0, format, GL_UNSIGNED_BYTE, texbuf.as_mut_ptr() as *const _ as *const c_void);
+        glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_MAG_FILTER, mob_texture_sampler.mag_filter.unwrap_or(GL_LINEAR) as i32);
+        glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_MIN_FILTER,
+                        mob_texture_sampler.min_filter.unwrap_or(GL_LINEAR_MIPMAP_LINEAR) as i32);
+        glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_WRAP_S, mob_texture_sampler.wrap_s as i32);
+        glTexParameteri(GL_TEXTURE_2D, GL_TEXTURE_WRAP_T, mob_texture_sampler.wrap_t as i32);
         glGenerateMipmap(GL_TEXTURE_2D);
         glBindTexture(GL_TEXTURE_2D, 0);
     }
@@ -662,17 +772,22 @@ fn ovr_render_frame(layer: &mut ovr::ovrLayerProjection2, renderer: &mut OvrRend
             1, GL_FALSE, &model_matrix as *const _ as *const GLfloat);
 
             // draw tabletop
-            glBindVertexArray(scene.tabletop.vertex_array_object);
-            glDrawElements(GL_TRIANGLES, scene.tabletop.index_count as GLsizei, GL_UNSIGNED_SHORT, std::ptr::null());
-            glBindVertexArray(0);
+            for (mesh_index, world_matrix) in scene.tabletop.instances.iter() {
+                let instance_model_matrix = matrix4x4_transpose(world_matrix);
+                glUniformMatrix4fv(program.uniform_location[shader::ProgramUniformIndex::UniformModelMatrix as usize],
+                                   1, GL_FALSE, &instance_model_matrix as *const _ as *const GLfloat);
+                for submesh in scene.tabletop.meshes[*mesh_index].submeshes.iter() {
+                    draw_geometry(submesh);
+                }
+            }
 
             // draw controller
             let controller_model_matrix = ovrMatrix4f_Transpose(&ovrMatrix4f_CreateFromQuaternion(&scene.controller_orientation));
             glUniformMatrix4fv(program.uniform_location[shader::ProgramUniformIndex::UniformModelMatrix as usize],
                                1, GL_FALSE, &controller_model_matrix as *const _ as *const GLfloat);
-            glBindVertexArray(scene.controller.vertex_array_object);
-            glDrawElements(GL_TRIANGLES, scene.controller.index_count as GLsizei, GL_UNSIGNED_SHORT, std::ptr::null());
-            glBindVertexArray(0);
+            for submesh in scene.controller.submeshes.iter() {
+                draw_geometry(submesh);
+            }
 
             // draw bear
             let mob_program = &scene.shader_programs[2];
@@ -690,12 +805,14 @@ fn ovr_render_frame(layer: &mut ovr::ovrLayerProjection2, renderer: &mut OvrRend
                              program.uniform_binding[shader::ProgramUniformIndex::UniformJointMatrices as usize] as u32,
                              scene.mob_jointbuf.buffer);
 
-            glActiveTexture(GL_TEXTURE0);
-            glBindTexture(GL_TEXTURE_2D, scene.mob_texture);
-            glBindVertexArray(scene.mob.vertex_array_object);
-            glDrawElements(GL_TRIANGLES, scene.mob.index_count as GLsizei, GL_UNSIGNED_SHORT, std::ptr::null());
-            glBindTexture(GL_TEXTURE_2D, 0);
-            glBindVertexArray(0);
+            if scene.mob_skinned_meshes.is_some() {
+                glActiveTexture(GL_TEXTURE0);
+                glBindTexture(GL_TEXTURE_2D, scene.mob_texture);
+                for geometry in scene.mob.iter() {
+                    draw_geometry(geometry);
+                }
+                glBindTexture(GL_TEXTURE_2D, 0);
+            }
 
             glUseProgram(0);
 
@@ -1,20 +1,227 @@
 use std::collections::HashMap;
+use std::rc::Rc;
 
 use math::matrix::float4x4;
 use math::quaternion::quaternion;
-use math::vector::{float2, float3};
+use math::vector::{float2, float3, float4};
 
 use crate::render::gl_geometry::VertexAttribs;
-use crate::anim::skeletal::{TRS};
+use crate::anim::skeletal::{cross, normalize, TRS};
 
+/// single drawable mesh primitive
 pub struct Mesh {
     pub attribs: VertexAttribs,
-    pub indices: Vec<u16>,
+    pub indices: MeshIndices,
+    pub mode: PrimitiveMode,
+    /// material index in the source file, indexes gltf::material::load_materials
+    pub material: Option<usize>,
+    /// morph targets (blend shapes), empty for unmorphed meshes
+    pub targets: Vec<MorphTarget>,
+    /// default morph target weights, one per target
+    pub weights: Vec<f32>,
+    /// transform from KHR_mesh_quantization integer positions to model space, the local transform of the
+    /// node instancing the mesh. None for float positions or when not exactly one node instances the mesh.
+    /// node world matrices already include it
+    pub dequantization: Option<float4x4>
+}
+
+impl Mesh {
+    /// write the base positions, normals and tangents plus the weighted target deltas into attribs,
+    /// and rebuild the binormals from the morphed normals and tangents.
+    /// attribs is a copy of this mesh's attribs, only the morphed attributes are overwritten
+    pub fn apply_morph_targets(&self, weights: &[f32], attribs: &mut VertexAttribs) {
+        attribs.position.copy_from_slice(&self.attribs.position);
+        attribs.normal.copy_from_slice(&self.attribs.normal);
+        attribs.tangent.copy_from_slice(&self.attribs.tangent);
+        for (target, &weight) in self.targets.iter().zip(weights.iter()) {
+            if weight == 0.0 {
+                continue;
+            }
+            for (position, delta) in attribs.position.iter_mut().zip(target.position.iter()) {
+                *position = *position + *delta * weight;
+            }
+            for (normal, delta) in attribs.normal.iter_mut().zip(target.normal.iter()) {
+                *normal = *normal + *delta * weight;
+            }
+            for (tangent, delta) in attribs.tangent.iter_mut().zip(target.tangent.iter()) {
+                *tangent = *tangent + *delta * weight;
+            }
+        }
+        for v in attribs.normal.iter_mut().chain(attribs.tangent.iter_mut()) {
+            *v = normalize(v);
+        }
+        // binormal = cross(normal, tangent) * handedness, the handedness of the unmorphed frame is kept
+        if attribs.binormal.len() == self.attribs.binormal.len() && !self.attribs.binormal.is_empty() {
+            for i in 0..attribs.binormal.len() {
+                let base = cross(&self.attribs.normal[i], &self.attribs.tangent[i]);
+                let base_binormal = self.attribs.binormal[i];
+                let handedness = if base.x * base_binormal.x + base.y * base_binormal.y + base.z * base_binormal.z < 0.0 { -1.0 } else { 1.0 };
+                attribs.binormal[i] = cross(&attribs.normal[i], &attribs.tangent[i]) * handedness;
+            }
+        }
+    }
+}
+
+/// per vertex attribute deltas of a morph target, each list is empty or one delta per vertex
+pub struct MorphTarget {
+    pub position: Vec<float3>,
+    pub normal: Vec<float3>,
+    pub tangent: Vec<float3>
+}
+
+/// primitive topology, values match the gltf (and gl) primitive modes
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PrimitiveMode {
+    Points = 0,
+    Lines = 1,
+    LineLoop = 2,
+    LineStrip = 3,
+    Triangles = 4,
+    TriangleStrip = 5,
+    TriangleFan = 6
+}
+
+impl PrimitiveMode {
+    pub fn from_gltf(mode: i64) -> Option<PrimitiveMode> {
+        match mode {
+            0 => Some(PrimitiveMode::Points),
+            1 => Some(PrimitiveMode::Lines),
+            2 => Some(PrimitiveMode::LineLoop),
+            3 => Some(PrimitiveMode::LineStrip),
+            4 => Some(PrimitiveMode::Triangles),
+            5 => Some(PrimitiveMode::TriangleStrip),
+            6 => Some(PrimitiveMode::TriangleFan),
+            _ => None
+        }
+    }
+}
+
+/// index buffer of a mesh primitive, kept in its source component type
+pub enum MeshIndices {
+    /// non-indexed primitive, vertices are drawn in order
+    None,
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+    U32(Vec<u32>)
+}
+
+impl MeshIndices {
+    pub fn len(&self) -> usize {
+        match self {
+            MeshIndices::None => 0,
+            MeshIndices::U8(indices) => indices.len(),
+            MeshIndices::U16(indices) => indices.len(),
+            MeshIndices::U32(indices) => indices.len()
+        }
+    }
+
+    pub fn get(&self, i: usize) -> u32 {
+        match self {
+            MeshIndices::None => i as u32,
+            MeshIndices::U8(indices) => indices[i] as u32,
+            MeshIndices::U16(indices) => indices[i] as u32,
+            MeshIndices::U32(indices) => indices[i]
+        }
+    }
+}
+
+/// node of an imported scene with its composed world transform
+pub struct NodeInstance {
+    /// node index in the source file
+    pub node: usize,
+    pub name: String,
+    /// mesh index in the source file
+    pub mesh: Option<usize>,
+    /// skin index in the source file
+    pub skin: Option<usize>,
+    /// transform to draw the loaded mesh with
+    pub world_matrix: float4x4
+}
+
+/// gltf pbr metallic-roughness material
+pub struct Material {
+    pub name: String,
+    /// linear rgba, multiplied with the base color texture
+    pub base_color_factor: float4,
+    pub base_color_texture: Option<TextureRef>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    /// metalness in b, roughness in g
+    pub metallic_roughness_texture: Option<TextureRef>,
+    pub normal_texture: Option<TextureRef>,
+    pub normal_scale: f32,
+    pub occlusion_texture: Option<TextureRef>,
+    pub occlusion_strength: f32,
+    pub emissive_texture: Option<TextureRef>,
+    pub emissive_factor: float3,
+    pub alpha_mode: AlphaMode,
+    /// alpha threshold for AlphaMode::Mask
+    pub alpha_cutoff: f32,
+    pub double_sided: bool
+}
+
+impl Default for Material {
+    /// the gltf default material, used by primitives without a material
+    fn default() -> Self {
+        return Material {
+            name: String::new(),
+            base_color_factor: float4::new(1.0, 1.0, 1.0, 1.0),
+            base_color_texture: None,
+            metallic_factor: 1.0,
+            roughness_factor: 1.0,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive_texture: None,
+            emissive_factor: float3::zero(),
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false
+        };
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum AlphaMode {
+    Opaque,
+    Mask,
+    Blend
+}
+
+/// texture slot of a material
+#[derive(Copy, Clone, Debug)]
+pub struct TextureRef {
+    /// image index in the source file
+    pub image: usize,
+    pub sampler: TextureSampler,
+    /// uv set, 0 = uv0, 1 = uv1
+    pub tex_coord: usize
+}
+
+/// texture filtering and wrapping, values are gl enums
+#[derive(Copy, Clone, Debug)]
+pub struct TextureSampler {
+    /// None lets the renderer choose
+    pub mag_filter: Option<u32>,
+    pub min_filter: Option<u32>,
+    pub wrap_s: u32,
+    pub wrap_t: u32
+}
+
+/// named mesh made of one or more primitives (submeshes)
+pub struct MeshGroup {
+    pub name: String,
+    pub primitives: Vec<Mesh>
 }
 
 pub struct SkeletalMesh {
+    /// name of the skinned node
+    pub name: String,
     pub mesh: Mesh,
-    pub rig: Rig,
+    /// shared by the meshes of one skin
+    pub rig: Rc<Rig>,
     pub joint_transforms: Vec<TRS>,
     pub joint_local_transforms: Vec<float4x4>,
     pub inverse_bind_matrices: Vec<float4x4>
@@ -32,6 +239,10 @@ pub struct Rig {
     pub joint_children: Vec<Vec<usize>>,
     /// flat joint parent indices array
     pub joint_parents: Vec<usize>,
+    /// root joint indices, the first root is joint zero
+    pub root_joints: Vec<usize>,
+    /// world transform of the node above each root joint, identity for scene roots
+    pub root_parent_transforms: Vec<float4x4>,
     /// source bone remap table
     pub remap_table: RigRemapTable
 }
@@ -41,7 +252,7 @@ pub struct Rig {
 pub struct Joint {
     /// joint index in rig joints array
     pub index: usize,
-    /// parent joint index in rig joints array (zero for root joint)
+    /// parent joint index in rig joints array (zero for root joints)
     pub parent_index: usize,
     pub translation: float3,
     pub rotation: quaternion,
@@ -1,8 +1,9 @@
-use crate::model::Mesh;
+use crate::model::{Mesh, MeshGroup, MeshIndices, NodeInstance, PrimitiveMode};
 use gles3::gles::*;
 use gl::types::*;
 use std::ffi::c_void;
 use crate::shader::{VertexAttributeLocationPosition, VertexAttributeLocationNormal, VertexAttributeLocationJointIndices, VertexAttributeLocationTangent, VertexAttributeLocationBinormal, VertexAttributeLocationColor, VertexAttributeLocationUv0, VertexAttributeLocationUv1, VertexAttributeLocationJointWeights};
+use math::matrix::float4x4;
 use math::vector::{float4, float2, float3, int4};
 
 pub const MAX_JOINTS: i32 = 64;
@@ -14,9 +15,26 @@ pub struct GlGeometry {
     pub vertex_array_object: u32,
     pub primitive_type: u32,//// GL_TRIANGLES / GL_LINES / GL_POINTS / etc
     pub vertex_count: i32,
-    pub index_count: i32
+    pub index_count: i32,
+    /// GL_UNSIGNED_BYTE / GL_UNSIGNED_SHORT / GL_UNSIGNED_INT, zero for non-indexed geometry
+    pub index_type: u32
 }
 
+/// one gl geometry per mesh primitive
+pub struct GlGeometrySet {
+    pub submeshes: Vec<GlGeometry>,
+    /// material index per submesh
+    pub materials: Vec<Option<usize>>
+}
+
+/// gl geometry of the meshes in an imported scene, each mesh is uploaded once
+pub struct GlSceneGeometry {
+    pub meshes: Vec<GlGeometrySet>,
+    /// index into meshes and world matrix per node instance
+    pub instances: Vec<(usize, float4x4)>
+}
+
+#[derive(Clone)]
 pub struct VertexAttribs {
     pub position: Vec<float3>,
     pub normal: Vec<float3>,
@@ -29,13 +47,12 @@ pub struct VertexAttribs {
     pub joint_weights: Vec<float4>
 }
 
-pub fn make_geometry(attribs: &VertexAttribs, indices: &Vec<u16>) -> GlGeometry {
+pub fn make_geometry(attribs: &VertexAttribs, indices: &MeshIndices, mode: PrimitiveMode) -> GlGeometry {
     let mut vao: GLuint = 0;
     let mut vertex_buffer: GLuint = 0;
     let mut index_buffer: GLuint = 0;
     unsafe {
         glGenBuffers(1, &mut vertex_buffer);
-        glGenBuffers(1, &mut index_buffer);
         glGenVertexArrays(1, &mut vao);
         glBindVertexArray(vao);
         glBindBuffer(GL_ARRAY_BUFFER, vertex_buffer);
@@ -53,9 +70,17 @@ pub fn make_geometry(attribs: &VertexAttribs, indices: &Vec<u16>) -> GlGeometry
 
         glBufferData(GL_ARRAY_BUFFER, packed.len() as isize, packed.as_ptr() as *const _ as *const c_void, GL_STATIC_DRAW);
 
-        glBindBuffer(GL_ELEMENT_ARRAY_BUFFER, index_buffer);
-        glBufferData(GL_ELEMENT_ARRAY_BUFFER, (indices.len() * 2) as GLsizeiptr,
-                     indices.as_ptr() as *const _ as *const c_void, GL_STATIC_DRAW);
+        let index_data: Option<(*const c_void, usize)> = match indices {
+            MeshIndices::None => None,
+            MeshIndices::U8(data) => Some((data.as_ptr() as *const c_void, data.len())),
+            MeshIndices::U16(data) => Some((data.as_ptr() as *const c_void, data.len() * 2)),
+            MeshIndices::U32(data) => Some((data.as_ptr() as *const c_void, data.len() * 4))
+        };
+        if let Some((data, size)) = index_data {
+            glGenBuffers(1, &mut index_buffer);
+            glBindBuffer(GL_ELEMENT_ARRAY_BUFFER, index_buffer);
+            glBufferData(GL_ELEMENT_ARRAY_BUFFER, size as GLsizeiptr, data, GL_STATIC_DRAW);
+        }
 
         glBindVertexArray(0);
 
@@ -74,10 +99,83 @@ pub fn make_geometry(attribs: &VertexAttribs, indices: &Vec<u16>) -> GlGeometry
         vertex_array_object: vao,
         vertex_buffer: vertex_buffer,
         index_buffer: index_buffer,
-        primitive_type: GL_TRIANGLES,
+        primitive_type: gl_primitive_type(mode),
         vertex_count: attribs.position.len() as i32,
-        index_count: indices.len() as i32
+        index_count: indices.len() as i32,
+        index_type: match indices {
+            MeshIndices::None => 0,
+            MeshIndices::U8(_) => GL_UNSIGNED_BYTE,
+            MeshIndices::U16(_) => GL_UNSIGNED_SHORT,
+            MeshIndices::U32(_) => GL_UNSIGNED_INT
+        }
+    }
+}
+
+/// re-upload the positions, normals and tangents of morphed attribs into geometry made from the same mesh.
+/// they are the first attributes packed by make_geometry, the rest of the vertex buffer is left as is
+pub fn update_morphed_geometry(geometry: &GlGeometry, attribs: &VertexAttribs) {
+    unsafe {
+        glBindBuffer(GL_ARRAY_BUFFER, geometry.vertex_buffer);
+        let mut offset: usize = 0;
+        for attrib in [&attribs.position, &attribs.normal, &attribs.tangent].iter() {
+            let size = attrib.len() * std::mem::size_of::<float3>();
+            if size > 0 {
+                glBufferSubData(GL_ARRAY_BUFFER, offset as GLintptr, size as GLsizeiptr, attrib.as_ptr() as *const c_void);
+            }
+            offset += size;
+        }
+        glBindBuffer(GL_ARRAY_BUFFER, 0);
+    }
+}
+
+pub fn gl_primitive_type(mode: PrimitiveMode) -> u32 {
+    match mode {
+        PrimitiveMode::Points => GL_POINTS,
+        PrimitiveMode::Lines => GL_LINES,
+        PrimitiveMode::LineLoop => GL_LINE_LOOP,
+        PrimitiveMode::LineStrip => GL_LINE_STRIP,
+        PrimitiveMode::Triangles => GL_TRIANGLES,
+        PrimitiveMode::TriangleStrip => GL_TRIANGLE_STRIP,
+        PrimitiveMode::TriangleFan => GL_TRIANGLE_FAN
+    }
+}
+
+/// draw indexed geometry with glDrawElements, non-indexed geometry with glDrawArrays
+pub fn draw_geometry(geometry: &GlGeometry) {
+    unsafe {
+        glBindVertexArray(geometry.vertex_array_object);
+        if geometry.index_buffer != 0 {
+            glDrawElements(geometry.primitive_type, geometry.index_count as GLsizei, geometry.index_type, std::ptr::null());
+        } else {
+            glDrawArrays(geometry.primitive_type, 0, geometry.vertex_count as GLsizei);
+        }
+        glBindVertexArray(0);
+    }
+}
+
+pub fn make_geometry_set(group: &MeshGroup) -> GlGeometrySet {
+    GlGeometrySet {
+        submeshes: group.primitives.iter().map(|mesh| make_geometry(&mesh.attribs, &mesh.indices, mesh.mode)).collect(),
+        materials: group.primitives.iter().map(|mesh| mesh.material).collect()
+    }
+}
+
+/// groups are the file's meshes (see load_mesh_groups), instances the imported scene nodes
+pub fn make_scene_geometry(groups: &Vec<MeshGroup>, instances: &Vec<NodeInstance>) -> GlSceneGeometry {
+    let mut mesh_slots: Vec<Option<usize>> = vec![None; groups.len()];
+    let mut scene = GlSceneGeometry { meshes: Vec::new(), instances: Vec::new() };
+    for instance in instances.iter() {
+        let mesh_index = match instance.mesh {
+            Some(mesh_index) => mesh_index,
+            None => continue
+        };
+        if mesh_slots[mesh_index].is_none() {
+            mesh_slots[mesh_index] = Some(scene.meshes.len());
+            scene.meshes.push(make_geometry_set(&groups[mesh_index]));
+        }
+        scene.instances.push((mesh_slots[mesh_index].unwrap(), instance.world_matrix));
     }
+    return scene;
 }
 
 pub fn pack_vertex_attribute<T>(packed: &mut Vec<u8>, attrib: &Vec<T>, gl_location: GLuint,